@@ -80,6 +80,14 @@ struct ExecOpt {
     #[clap(short = 't', long, default_value = "1000")]
     time_limit: u32,
 
+    /// Max number of CPUs used at once (can be fractional)
+    #[clap(long)]
+    cpus: Option<f64>,
+
+    /// CPUs to pin isolated process to (e.g. 0,2)
+    #[clap(long, use_delimiter = true)]
+    cpuset: Vec<u32>,
+
     /// Print parsed argv
     #[clap(long)]
     dump_argv: bool,
@@ -138,10 +146,22 @@ async fn main() {
         .new_sandbox(minion::SandboxOptions {
             max_alive_process_count: options.num_processes.min(u32::max_value() as usize) as u32,
             memory_limit: options.memory_limit as u64,
+            memory_high: None,
+            swap: minion::SwapPolicy::Deny,
             isolation_root: options.isolation_root.into(),
             shared_items: options.exposed_paths,
             cpu_time_limit: Duration::from_millis(u64::from(options.time_limit)),
             real_time_limit: Duration::from_millis(u64::from(options.time_limit * 3)),
+            cpu_bandwidth: options.cpus.map(minion::CpuBandwidth::cores),
+            cpuset: if options.cpuset.is_empty() {
+                None
+            } else {
+                Some(minion::Cpuset {
+                    cpus: options.cpuset,
+                    mems: Vec::new(),
+                })
+            },
+            io_limits: Vec::new(),
         })
         .unwrap();
 
@@ -166,6 +186,7 @@ async fn main() {
         },
         extra_inherit: Vec::new(),
         pwd: options.pwd.into(),
+        platform: Default::default(),
     };
     if options.dump_minion_params {
         println!("{:#?}", args);
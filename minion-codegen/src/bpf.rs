@@ -0,0 +1,138 @@
+//! Seccomp filter definitions for supported architectures.
+//! Generated filters check architecture first, so that syscalls made
+//! using other ABI (e.g. i386 `int 0x80` or x32 on x86_64) can not
+//! bypass the filter. Instructions are emitted by the module shared
+//! with the runtime policy compiler.
+use crate::emit::{self, SECCOMP_RET_ALLOW, SECCOMP_RET_ERRNO, SECCOMP_RET_KILL_PROCESS};
+use serde::Deserialize;
+
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Allow,
+    Errno(u16),
+    /// Kills whole process
+    Kill,
+}
+
+impl Action {
+    fn value(self) -> u32 {
+        match self {
+            Action::Allow => SECCOMP_RET_ALLOW,
+            Action::Errno(code) => SECCOMP_RET_ERRNO | u32::from(code),
+            Action::Kill => SECCOMP_RET_KILL_PROCESS,
+        }
+    }
+}
+
+/// Comparison of syscall argument with a constant, as unsigned 64-bit integers.
+#[derive(Deserialize, Copy, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ArgOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// `arg & mask == value`
+    MaskedEq {
+        mask: u64,
+    },
+}
+
+#[derive(Deserialize, Copy, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ArgCondition {
+    /// Argument number, from 0 to 5
+    pub index: u8,
+    pub op: ArgOp,
+    pub value: u64,
+}
+
+/// Applies `action` if syscall number is `nr` and all `args` conditions hold.
+pub struct Rule {
+    pub nr: u32,
+    pub args: Vec<ArgCondition>,
+    pub action: Action,
+}
+
+pub struct Arch {
+    /// Value of `target_arch`
+    pub name: &'static str,
+    /// `AUDIT_ARCH_*` constant
+    pub audit_arch: u32,
+    /// Syscalls with this bit set use other ABI (x32 on x86_64)
+    pub abi_bit: Option<u32>,
+    pub syscalls: &'static [(&'static str, u32)],
+}
+
+impl Arch {
+    pub fn syscall(&self, name: &str) -> Option<u32> {
+        self.syscalls
+            .iter()
+            .find(|(syscall, _)| *syscall == name)
+            .map(|(_, nr)| *nr)
+    }
+
+    /// Architecture codegen is running on
+    pub fn native() -> Option<&'static Arch> {
+        ARCHES
+            .iter()
+            .find(|arch| arch.name == std::env::consts::ARCH)
+    }
+}
+
+pub const ARCHES: &[Arch] = &[
+    Arch {
+        name: "x86_64",
+        audit_arch: 0xc000_003e,
+        abi_bit: Some(0x4000_0000),
+        syscalls: crate::syscalls::X86_64,
+    },
+    Arch {
+        name: "x86",
+        audit_arch: 0x4000_0003,
+        abi_bit: None,
+        syscalls: crate::syscalls::X86,
+    },
+    Arch {
+        name: "aarch64",
+        audit_arch: 0xc000_00b7,
+        abi_bit: None,
+        syscalls: crate::syscalls::AARCH64,
+    },
+];
+
+/// Compiles filter which applies action of the first matching rule,
+/// or `default` if no rule matches. Processes using foreign ABI are killed.
+pub fn compile(arch: &Arch, default: Action, rules: &[Rule]) -> anyhow::Result<Vec<u8>> {
+    let rules = rules
+        .iter()
+        .map(|rule| emit::Rule {
+            nr: rule.nr,
+            conditions: rule
+                .args
+                .iter()
+                .map(|cond| emit::Condition {
+                    index: cond.index,
+                    cmp: match cond.op {
+                        ArgOp::Eq => emit::Cmp::Eq,
+                        ArgOp::Ne => emit::Cmp::Ne,
+                        ArgOp::Lt => emit::Cmp::Lt,
+                        ArgOp::Le => emit::Cmp::Le,
+                        ArgOp::Gt => emit::Cmp::Gt,
+                        ArgOp::Ge => emit::Cmp::Ge,
+                        ArgOp::MaskedEq { mask } => emit::Cmp::MaskedEq(mask),
+                    },
+                    value: cond.value,
+                })
+                .collect(),
+            ret: rule.action.value(),
+        })
+        .collect::<Vec<_>>();
+    let mut out = Vec::new();
+    emit::emit_arch_check(&mut out, arch.audit_arch, arch.abi_bit);
+    emit::emit_rules(&mut out, &rules, default.value(), true).map_err(anyhow::Error::msg)?;
+    Ok(out)
+}
@@ -1,10 +1,25 @@
 use std::sync::atomic::{AtomicBool, Ordering};
 
+mod bpf;
+// shared with the runtime policy compiler
+#[allow(dead_code)]
+#[path = "../../src/linux/seccomp/emit.rs"]
+mod emit;
 mod seccomp_policies;
+mod syscalls;
 
 static DID_WRITES: AtomicBool = AtomicBool::new(false);
 
 fn main() -> anyhow::Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if let [cmd, input, output] = args.as_slice() {
+        if cmd == "allowlist" {
+            return seccomp_policies::gen_allowlist(input, output);
+        }
+    }
+    if !args.is_empty() {
+        anyhow::bail!("usage: minion-codegen [allowlist <syscalls.json> <policy.bpf>]");
+    }
     seccomp_policies::gen()?;
 
     if DID_WRITES.load(Ordering::SeqCst) {
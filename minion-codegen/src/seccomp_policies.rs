@@ -1,61 +1,157 @@
-use seccomp::{Action, Context, Rule};
-use std::{fmt::Write, os::unix::io::AsRawFd};
-const DANGEROUS_SYSCALLS: &[i64] = &[
-    libc::SYS_ptrace,
-    libc::SYS_process_vm_readv,
-    libc::SYS_process_vm_writev,
-    libc::SYS_kill,
-];
+use crate::bpf::{Action, Arch, ArgCondition, Rule, ARCHES};
+use serde::Deserialize;
+use std::{convert::TryFrom, fmt::Write, path::Path};
 
-const SAFE_SYSCALLS: &[i64] = &[
-    libc::SYS_exit,
-    libc::SYS_fork,
-    libc::SYS_clone,
-    libc::SYS_read,
-    libc::SYS_write,
-    libc::SYS_wait4,
-    libc::SYS_waitid,
-    libc::SYS_execve,
-];
+/// Directory with policy definitions, relative to repository root
+const POLICIES_DIR: &str = "minion-codegen/policies";
 
-fn new_deny_dangerous() -> anyhow::Result<Vec<u8>> {
-    let mut cx = Context::default(Action::Allow)?;
-
-    for &call in DANGEROUS_SYSCALLS {
-        cx.add_rule(Rule::new(call as usize, None, Action::Errno(libc::EPERM)))?;
-    }
-    from_cx(cx)
+/// Policy definition. Codegen generates a `PredefinedPolicy` variant
+/// and a filter for each architecture from it.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyDef {
+    /// Kebab-case name, e.g. `deny-dangerous`
+    name: String,
+    /// Used as documentation for the `PredefinedPolicy` variant
+    description: String,
+    default_action: Action,
+    #[serde(default)]
+    rules: Vec<RuleDef>,
 }
-fn new_unrestricted() -> anyhow::Result<Vec<u8>> {
-    let cx = Context::default(Action::Allow)?;
 
-    from_cx(cx)
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleDef {
+    /// Syscall name, e.g. `read`
+    syscall: String,
+    action: Action,
+    #[serde(default)]
+    args: Vec<ArgCondition>,
 }
 
-fn new_pure() -> anyhow::Result<Vec<u8>> {
-    let mut cx = Context::default(Action::Errno(libc::EPERM))?;
+impl PolicyDef {
+    fn const_name(&self) -> String {
+        self.name.replace('-', "_").to_uppercase()
+    }
+
+    fn variant_name(&self) -> String {
+        self.name
+            .split('-')
+            .map(|word| {
+                let mut chars = word.chars();
+                chars
+                    .next()
+                    .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    /// Syscalls which do not exist on the architecture are skipped:
+    /// they can not be called anyway.
+    fn compile(&self, arch: &Arch) -> anyhow::Result<Vec<u8>> {
+        let rules = self
+            .rules
+            .iter()
+            .filter_map(|rule| {
+                arch.syscall(&rule.syscall).map(|nr| Rule {
+                    nr,
+                    args: rule.args.clone(),
+                    action: rule.action,
+                })
+            })
+            .collect::<Vec<_>>();
+        crate::bpf::compile(arch, self.default_action, &rules)
+    }
+}
 
-    for &call in SAFE_SYSCALLS {
-        cx.add_rule(Rule::new(call as usize, None, Action::Allow))?;
+fn load_policy(path: &Path) -> anyhow::Result<PolicyDef> {
+    let data = std::fs::read(path)?;
+    let policy: PolicyDef = serde_json::from_slice(&data)?;
+    let valid_name = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-';
+    anyhow::ensure!(
+        !policy.name.is_empty() && policy.name.chars().all(valid_name),
+        "invalid policy name {:?}",
+        policy.name
+    );
+    for rule in &policy.rules {
+        anyhow::ensure!(
+            ARCHES
+                .iter()
+                .any(|arch| arch.syscall(&rule.syscall).is_some()),
+            "unknown syscall {}",
+            rule.syscall
+        );
     }
-    from_cx(cx)
+    Ok(policy)
 }
 
-fn from_cx(cx: Context) -> anyhow::Result<Vec<u8>> {
-    let f = tempfile::NamedTempFile::new()?;
-    let fd = f.as_file().as_raw_fd();
+fn load_policies() -> anyhow::Result<Vec<PolicyDef>> {
+    let mut paths = std::fs::read_dir(std::env::current_dir()?.join(POLICIES_DIR))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.retain(|path| path.extension().map_or(false, |ext| ext == "json"));
+    paths.sort();
+    let mut policies = Vec::new();
+    for path in paths {
+        let policy =
+            load_policy(&path).map_err(|err| anyhow::anyhow!("{}: {:#}", path.display(), err))?;
+        anyhow::ensure!(
+            policies
+                .iter()
+                .all(|other: &PolicyDef| other.name != policy.name),
+            "policy {} is defined twice",
+            policy.name
+        );
+        policies.push(policy);
+    }
+    Ok(policies)
+}
 
-    cx.export(fd)?;
-    let data = std::fs::read(f.path())?;
+/// Denies everything except given syscalls (and syscalls required
+/// for sandbox startup).
+fn new_allowlist(arch: &Arch, syscalls: &[i64]) -> anyhow::Result<Vec<u8>> {
+    let mut numbers = syscalls
+        .iter()
+        .map(|&nr| u32::try_from(nr).map_err(|_| anyhow::anyhow!("invalid syscall number {}", nr)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    numbers.extend(arch.syscall("execve"));
+    numbers.extend(arch.syscall("exit_group"));
+    numbers.sort_unstable();
+    numbers.dedup();
+    let rules = numbers
+        .into_iter()
+        .map(|nr| Rule {
+            nr,
+            args: Vec::new(),
+            action: Action::Allow,
+        })
+        .collect::<Vec<_>>();
+    crate::bpf::compile(arch, Action::Errno(libc::EPERM as u16), &rules)
+}
 
-    Ok(data)
+/// Converts syscalls recorded with `SeccompPolicy::Audit` (JSON array
+/// of numbers, as returned by `Sandbox::used_syscalls`) to policy which
+/// can be passed as `SeccompPolicy::Manual`.
+/// Syscall numbers are for the architecture codegen is running on.
+pub fn gen_allowlist(input: &str, output: &str) -> anyhow::Result<()> {
+    let arch = Arch::native().ok_or_else(|| anyhow::anyhow!("unsupported architecture"))?;
+    let data = std::fs::read_to_string(input)?;
+    let syscalls = serde_json::from_str::<Vec<i64>>(&data)
+        .map_err(|err| anyhow::anyhow!("{}: invalid syscall list: {}", input, err))?;
+    std::fs::write(output, new_allowlist(arch, &syscalls)?)?;
+    Ok(())
 }
 
 fn export_policy(name: &str, data: &[u8]) -> String {
-    format!("pub (in super) const {}: &[u8] = &{:?};", name, data)
+    format!(
+        "    pub(in crate::linux::seccomp) const {}: &[u8] = &{:?};",
+        name, data
+    )
 }
 
 pub fn gen() -> anyhow::Result<()> {
+    let policies = load_policies()?;
     let mut contents = String::new();
     writeln!(contents, "// this is @generated file").unwrap();
     writeln!(
@@ -63,20 +159,120 @@ pub fn gen() -> anyhow::Result<()> {
         "// codegen: minion-codegen/src/seccomp_policies.rs"
     )
     .unwrap();
+    writeln!(contents, "// policies: {}", POLICIES_DIR).unwrap();
+    writeln!(contents, "use serde::{{Deserialize, Serialize}};").unwrap();
+    writeln!(contents).unwrap();
+    writeln!(contents, "/// Policies defined in `{}`.", POLICIES_DIR).unwrap();
+    writeln!(
+        contents,
+        "#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]"
+    )
+    .unwrap();
+    writeln!(contents, "#[non_exhaustive]").unwrap();
+    writeln!(contents, "pub enum PredefinedPolicy {{").unwrap();
+    for policy in &policies {
+        for line in policy.description.lines() {
+            writeln!(contents, "    /// {}", line).unwrap();
+        }
+        writeln!(contents, "    {},", policy.variant_name()).unwrap();
+    }
+    writeln!(contents, "}}").unwrap();
+    writeln!(contents).unwrap();
+    writeln!(contents, "impl PredefinedPolicy {{").unwrap();
     writeln!(
         contents,
-        "{}",
-        export_policy("UNRESTRICTED", &new_unrestricted()?)
+        "    /// Returns filter for the target architecture"
     )
     .unwrap();
     writeln!(
         contents,
-        "{}",
-        export_policy("DENY_DANGEROUS", &new_deny_dangerous()?)
+        "    pub(super) fn filter(self) -> &'static [u8] {{"
     )
     .unwrap();
-    writeln!(contents, "{}", export_policy("PURE", &new_pure()?)).unwrap();
+    writeln!(contents, "        match self {{").unwrap();
+    for policy in &policies {
+        writeln!(
+            contents,
+            "            PredefinedPolicy::{} => native::{},",
+            policy.variant_name(),
+            policy.const_name()
+        )
+        .unwrap();
+    }
+    writeln!(contents, "        }}").unwrap();
+    writeln!(contents, "    }}").unwrap();
+    writeln!(contents, "}}").unwrap();
+    writeln!(contents).unwrap();
+    for arch in ARCHES {
+        writeln!(contents, "#[cfg(target_arch = \"{}\")]", arch.name).unwrap();
+        writeln!(contents, "use self::{} as native;", arch.name).unwrap();
+    }
+    let arch_names = ARCHES
+        .iter()
+        .map(|arch| format!("target_arch = \"{}\"", arch.name))
+        .collect::<Vec<_>>();
+    writeln!(contents, "#[cfg(not(any({})))]", arch_names.join(", ")).unwrap();
+    writeln!(contents, "mod native {{").unwrap();
+    for policy in &policies {
+        writeln!(contents, "{}", export_policy(&policy.const_name(), &[])).unwrap();
+    }
+    writeln!(contents, "}}").unwrap();
+    for arch in ARCHES {
+        writeln!(contents).unwrap();
+        writeln!(contents, "#[cfg(target_arch = \"{}\")]", arch.name).unwrap();
+        writeln!(contents, "pub(super) mod {} {{", arch.name).unwrap();
+        for policy in &policies {
+            let filter = policy
+                .compile(arch)
+                .map_err(|err| anyhow::anyhow!("{} ({}): {:#}", policy.name, arch.name, err))?;
+            writeln!(contents, "{}", export_policy(&policy.const_name(), &filter)).unwrap();
+        }
+        writeln!(contents, "}}").unwrap();
+    }
 
     crate::put_file("src/linux/seccomp/gen.rs", &contents)?;
+    gen_syscall_names()?;
+    Ok(())
+}
+
+/// Generates syscall names table, used by the runtime to describe
+/// violations and to disassemble filters.
+fn gen_syscall_names() -> anyhow::Result<()> {
+    let mut contents = String::new();
+    writeln!(contents, "// this is @generated file").unwrap();
+    writeln!(contents, "// codegen: minion-codegen/src/syscalls.rs").unwrap();
+    writeln!(contents, "//! Syscall names, indexed by syscall number.").unwrap();
+    writeln!(
+        contents,
+        "//! Empty string means that the number is not used."
+    )
+    .unwrap();
+    for arch in ARCHES {
+        let len = arch
+            .syscalls
+            .iter()
+            .map(|&(_, nr)| nr + 1)
+            .max()
+            .unwrap_or(0);
+        let mut names = vec![""; len as usize];
+        for &(name, nr) in arch.syscalls {
+            names[nr as usize] = name;
+        }
+        writeln!(contents).unwrap();
+        writeln!(contents, "#[cfg(target_arch = \"{}\")]", arch.name).unwrap();
+        writeln!(contents, "pub(super) const NATIVE: &[&str] = &[").unwrap();
+        for name in names {
+            writeln!(contents, "    {:?},", name).unwrap();
+        }
+        writeln!(contents, "];").unwrap();
+    }
+    let arch_names = ARCHES
+        .iter()
+        .map(|arch| format!("target_arch = \"{}\"", arch.name))
+        .collect::<Vec<_>>();
+    writeln!(contents).unwrap();
+    writeln!(contents, "#[cfg(not(any({})))]", arch_names.join(", ")).unwrap();
+    writeln!(contents, "pub(super) const NATIVE: &[&str] = &[];").unwrap();
+    crate::put_file("src/linux/seccomp/syscall_names.rs", &contents)?;
     Ok(())
 }
@@ -159,6 +159,42 @@ pub unsafe extern "C" fn minion_sandbox_check_real_tle(
     }
 }
 
+/// # Safety
+/// `out` must be valid
+#[no_mangle]
+pub unsafe extern "C" fn minion_sandbox_check_pids_limit_exceeded(
+    sandbox: &Sandbox,
+    out: *mut bool,
+) -> ErrorCode {
+    match sandbox.0.check_pids_limit_exceeded() {
+        Ok(st) => {
+            unsafe {
+                out.write(st);
+            }
+            ErrorCode::Ok
+        }
+        Err(_) => ErrorCode::Minion,
+    }
+}
+
+/// # Safety
+/// `out` must be valid
+#[no_mangle]
+pub unsafe extern "C" fn minion_sandbox_check_memory_limit_exceeded(
+    sandbox: &Sandbox,
+    out: *mut bool,
+) -> ErrorCode {
+    match sandbox.0.check_memory_limit_exceeded() {
+        Ok(st) => {
+            unsafe {
+                out.write(st);
+            }
+            ErrorCode::Ok
+        }
+        Err(_) => ErrorCode::Minion,
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn minion_sandbox_kill(sandbox: &Sandbox) -> ErrorCode {
     match sandbox.0.kill() {
@@ -167,6 +203,30 @@ pub extern "C" fn minion_sandbox_kill(sandbox: &Sandbox) -> ErrorCode {
     }
 }
 
+/// Waits at most `timeout_ms` milliseconds until processes of the killed
+/// sandbox exit. Sets `out` to false on timeout.
+/// # Safety
+/// `out` must be valid
+#[no_mangle]
+pub unsafe extern "C" fn minion_sandbox_wait_killed(
+    sandbox: &Sandbox,
+    timeout_ms: u64,
+    out: *mut bool,
+) -> ErrorCode {
+    match sandbox
+        .0
+        .wait_killed(std::time::Duration::from_millis(timeout_ms))
+    {
+        Ok(exited) => {
+            unsafe {
+                out.write(exited);
+            }
+            ErrorCode::Ok
+        }
+        Err(_) => ErrorCode::Minion,
+    }
+}
+
 /// # Safety
 /// Provided arguments must be well-formed
 #[no_mangle]
@@ -198,6 +258,8 @@ pub unsafe extern "C" fn minion_sandbox_create(
     let opts = minion::SandboxOptions {
         max_alive_process_count: options.process_limit as _,
         memory_limit: u64::from(options.memory_limit),
+        memory_high: None,
+        swap: minion::SwapPolicy::Deny,
         cpu_time_limit: std::time::Duration::new(
             options.cpu_time_limit.seconds.into(),
             options.cpu_time_limit.nanoseconds,
@@ -206,6 +268,9 @@ pub unsafe extern "C" fn minion_sandbox_create(
             options.real_time_limit.seconds.into(),
             options.real_time_limit.nanoseconds,
         ),
+        cpu_bandwidth: None,
+        cpuset: None,
+        io_limits: Vec::new(),
         isolation_root,
         shared_items,
     };
@@ -341,6 +406,7 @@ pub unsafe extern "C" fn minion_cp_spawn(
             stdio,
             extra_inherit: Vec::new(),
             pwd: get_string(options.workdir).into(),
+            platform: Default::default(),
         }
     };
     let cp = backend.0.spawn(options, sandbox).unwrap();
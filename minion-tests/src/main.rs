@@ -20,6 +20,7 @@ pub struct CompletedChild<'a> {
     // stdin: &'a mut dyn std::io::Write,
     pub stdout: &'a mut dyn std::io::Read,
     pub stderr: &'a mut dyn std::io::Read,
+    pub security_violation: Option<minion::SecurityViolation>,
 }
 
 impl<'a> CompletedChild<'a> {
@@ -28,6 +29,7 @@ impl<'a> CompletedChild<'a> {
             exit_code: self.exit_code,
             stdout: &mut *self.stdout,
             stderr: &mut *self.stderr,
+            security_violation: self.security_violation.clone(),
         }
     }
 }
@@ -54,12 +56,26 @@ pub trait TestCase: Send + Sync {
     fn real_time_limit(&self) -> std::time::Duration {
         std::time::Duration::from_secs(2)
     }
+    /// Overrides CPU bandwidth limit
+    fn cpu_bandwidth(&self) -> Option<minion::CpuBandwidth> {
+        None
+    }
+    /// Overrides cpuset
+    fn cpuset(&self) -> Option<minion::Cpuset> {
+        None
+    }
     /// Overrides process limit
     fn process_count_limit(&self) -> u32 {
         1
     }
+    /// Items shared with sandbox in addition to test binary
+    fn shared_items(&self) -> Vec<minion::SharedItem> {
+        Vec::new()
+    }
     /// A way to modify child settings
     fn modify_settings(&self, _settings: &mut minion::ChildProcessOptions) {}
+    /// A way to modify backend settings
+    fn modify_backend_settings(&self, _settings: &mut minion::linux::Settings) {}
     /// If this method returns false, test is skipped
     fn filter(&self, _profile: &str) -> bool {
         true
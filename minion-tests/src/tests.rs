@@ -14,8 +14,31 @@ fn get_tests() -> Vec<&'static dyn TestCase> {
         extend_lifetime(simple::TIdle),
         extend_lifetime(simple::TRet1),
         extend_lifetime(simple::TOom),
+        extend_lifetime(simple::TSharedMemoryOom),
+        extend_lifetime(simple::TNoexec),
         extend_lifetime(simple::TSecurity),
         extend_lifetime(simple::TInherit),
+        extend_lifetime(simple::TFsChanges),
+        extend_lifetime(simple::TFileTransfer),
+        extend_lifetime(simple::TExpose),
+        extend_lifetime(simple::TSymlinkedMountTarget),
+        extend_lifetime(simple::TSeccompOverride),
+        extend_lifetime(simple::TSeccompOverridePure),
+        extend_lifetime(simple::TNotifySocketSealed),
+        extend_lifetime(simple::TThreadViolation),
+        extend_lifetime(simple::TDisassemble),
+        extend_lifetime(simple::TCpuBandwidth),
+        extend_lifetime(simple::TInvalidOptions),
+        extend_lifetime(simple::TCpuset),
+        extend_lifetime(simple::TPidsLimit),
+        extend_lifetime(simple::TSingleProcess),
+        extend_lifetime(simple::TRootlessFork),
+        extend_lifetime(simple::TRootlessCgroupV2),
+        extend_lifetime(simple::TStaleCleanup),
+        extend_lifetime(simple::TKill),
+        extend_lifetime(simple::TIoCounters),
+        extend_lifetime(simple::TMemoryPeak),
+        extend_lifetime(simple::TCpuTimeSplit),
     ]
 }
 
@@ -156,6 +156,78 @@ impl crate::TestCase for TOom {
     }
 }
 
+pub(crate) struct TSharedMemoryOom;
+impl crate::TestCase for TSharedMemoryOom {
+    fn name(&self) -> &'static str {
+        "test_shared_memory_out_of_memory"
+    }
+    fn description(&self) -> &'static str {
+        "launches program that consumes more memory than allowed \
+        using shared mapping (which is not limited by RLIMIT_DATA) \
+        and checks that program was killed"
+    }
+    fn test(&self) -> ! {
+        unsafe {
+            const ALLOC_SIZE: usize = 1 << 26;
+            let mem = nix::sys::mman::mmap(
+                std::ptr::null_mut(),
+                ALLOC_SIZE,
+                nix::sys::mman::ProtFlags::PROT_READ | nix::sys::mman::ProtFlags::PROT_WRITE,
+                nix::sys::mman::MapFlags::MAP_SHARED | nix::sys::mman::MapFlags::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+            .unwrap() as *mut u8;
+            std::ptr::write_bytes(mem, 1, ALLOC_SIZE);
+            // give watchdog time to notice
+            std::thread::sleep(std::time::Duration::from_secs(5));
+            // should be unreachable
+            std::process::exit(0);
+        }
+    }
+    fn check(&self, cp: crate::CompletedChild, sb: &dyn Sandbox) {
+        super::assert_killed(cp);
+        assert!(!sb.check_cpu_tle().unwrap());
+        assert!(!sb.check_real_tle().unwrap());
+        assert!(sb.check_memory_limit_exceeded().unwrap());
+    }
+    fn real_time_limit(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(20)
+    }
+    fn time_limit(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(10)
+    }
+}
+
+pub(crate) struct TNoexec;
+impl crate::TestCase for TNoexec {
+    fn name(&self) -> &'static str {
+        "test_noexec"
+    }
+    fn description(&self) -> &'static str {
+        "checks that files from item shared with noexec flag can not be executed"
+    }
+    fn test(&self) -> ! {
+        let path = std::ffi::CString::new("/noexec/me").unwrap();
+        let err = nix::unistd::execv(&path, &[&path]).unwrap_err();
+        assert_eq!(err.as_errno(), Some(nix::errno::Errno::EACCES));
+        exit(0)
+    }
+    fn check(&self, mut cp: crate::CompletedChild, _sb: &dyn Sandbox) {
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode::OK);
+        super::assert_empty(cp.stderr);
+    }
+    fn shared_items(&self) -> Vec<minion::SharedItem> {
+        vec![minion::SharedItem {
+            id: None,
+            src: std::env::current_exe().unwrap(),
+            dest: "/noexec/me".into(),
+            kind: minion::SharedItemKind::Readonly,
+            flags: vec!["noexec".to_string()],
+        }]
+    }
+}
+
 pub(crate) struct TSecurity;
 impl crate::TestCase for TSecurity {
     fn name(&self) -> &'static str {
@@ -235,6 +307,972 @@ impl crate::TestCase for TInherit {
     }
 }
 
+pub(crate) struct TFsChanges;
+impl crate::TestCase for TFsChanges {
+    fn name(&self) -> &'static str {
+        "test_filesystem_changes"
+    }
+
+    fn description(&self) -> &'static str {
+        "verifies that files created by isolated program are reported"
+    }
+
+    fn test(&self) -> ! {
+        std::fs::write("/output.txt", b"hello").unwrap();
+        exit(0)
+    }
+
+    fn check(&self, mut cp: crate::CompletedChild, sb: &dyn Sandbox) {
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode::OK);
+        let changes = sb.filesystem_changes().unwrap();
+        let change = changes
+            .iter()
+            .find(|ch| ch.path == std::path::Path::new("/output.txt"))
+            .expect("created file was not reported");
+        assert_eq!(change.kind, minion::FilesystemChangeKind::Added);
+        assert_eq!(change.old_size, None);
+        assert_eq!(change.new_size, Some(5));
+        // `/me` is a mount target, it must not be reported
+        assert!(!changes
+            .iter()
+            .any(|ch| ch.path == std::path::Path::new("/me")));
+    }
+}
+
+pub(crate) struct TFileTransfer;
+impl crate::TestCase for TFileTransfer {
+    fn name(&self) -> &'static str {
+        "test_file_transfer"
+    }
+
+    fn description(&self) -> &'static str {
+        "verifies that files can be read from and written to sandbox"
+    }
+
+    fn test(&self) -> ! {
+        std::fs::write("/output.txt", b"hello").unwrap();
+        std::os::unix::fs::symlink("/output.txt", "/link.txt").unwrap();
+        exit(0)
+    }
+
+    fn check(&self, mut cp: crate::CompletedChild, sb: &dyn Sandbox) {
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode::OK);
+        let path = std::path::Path::new("/output.txt");
+        assert_eq!(sb.read_file(path, 1024).unwrap(), b"hello");
+        assert_eq!(sb.read_file(path, 3).unwrap(), b"hel");
+        // symlinks must not be followed
+        assert!(sb
+            .read_file(std::path::Path::new("/link.txt"), 1024)
+            .is_err());
+        assert!(sb
+            .read_file(std::path::Path::new("/../output.txt"), 1024)
+            .is_err());
+
+        sb.write_file(path, b"bye", 0o600).unwrap();
+        assert_eq!(sb.read_file(path, 1024).unwrap(), b"bye");
+    }
+}
+
+pub(crate) struct TExpose;
+impl crate::TestCase for TExpose {
+    fn name(&self) -> &'static str {
+        "test_expose"
+    }
+
+    fn description(&self) -> &'static str {
+        "verifies that items can be exposed to and removed from running sandbox"
+    }
+
+    fn test(&self) -> ! {
+        // mount target must not be controlled by sandboxed processes
+        std::os::unix::fs::symlink("/proc/1/root", "/evil").unwrap();
+        exit(0)
+    }
+
+    fn check(&self, mut cp: crate::CompletedChild, sb: &dyn Sandbox) {
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode::OK);
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join("data.txt"), b"exposed").unwrap();
+        let item = |dest: &str| minion::SharedItem {
+            id: None,
+            src: dir.path().to_path_buf(),
+            dest: dest.into(),
+            kind: minion::SharedItemKind::Readonly,
+            flags: vec![],
+        };
+        let path = std::path::Path::new("/exposed/data.txt");
+
+        sb.expose(item("/exposed")).unwrap();
+        assert_eq!(sb.read_file(path, 1024).unwrap(), b"exposed");
+        // bind target must not be resolved through symlinks
+        assert!(sb.expose(item("/evil/tmp/escaped")).is_err());
+        // only items exposed by `expose` can be removed
+        assert!(sb.unexpose(std::path::Path::new("/me")).is_err());
+        assert!(sb.unexpose(std::path::Path::new("/proc")).is_err());
+        assert!(sb.unexpose(std::path::Path::new("/evil")).is_err());
+
+        sb.unexpose(std::path::Path::new("/exposed")).unwrap();
+        assert!(sb.read_file(path, 1024).is_err());
+        assert!(sb.unexpose(std::path::Path::new("/exposed")).is_err());
+    }
+
+    fn filter(&self, profile: &str) -> bool {
+        // mount tree is created on the host, which requires root
+        profile != "prlimit-rootless" && minion::linux::check::new_mount_api_supported()
+    }
+}
+
+pub(crate) struct TSymlinkedMountTarget;
+impl crate::TestCase for TSymlinkedMountTarget {
+    fn name(&self) -> &'static str {
+        "test_symlinked_mount_target"
+    }
+
+    fn description(&self) -> &'static str {
+        "verifies that shared items are not mounted through symlinks \
+        in isolation root"
+    }
+
+    fn test(&self) -> ! {
+        exit(0)
+    }
+
+    fn check(&self, mut cp: crate::CompletedChild, _: &dyn Sandbox) {
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode::OK);
+        let root = tempfile::TempDir::new().unwrap();
+        let outside = tempfile::TempDir::new().unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.path().join("evil")).unwrap();
+        let item = |src: &std::path::Path, dest: &str| minion::SharedItem {
+            id: None,
+            src: src.to_path_buf(),
+            dest: dest.into(),
+            kind: minion::SharedItemKind::Readonly,
+            flags: vec![],
+        };
+        let backend = minion::linux::LinuxBackend::new(crate::worker::backend_settings()).unwrap();
+        let res = minion::Backend::new_sandbox(
+            &backend,
+            minion::SandboxOptions {
+                cpu_time_limit: self.time_limit(),
+                real_time_limit: self.real_time_limit(),
+                cpu_bandwidth: None,
+                cpuset: None,
+                io_limits: Vec::new(),
+                max_alive_process_count: 1,
+                memory_limit: 1 << 26,
+                memory_high: None,
+                swap: minion::SwapPolicy::Deny,
+                isolation_root: root.path().to_path_buf(),
+                shared_items: vec![item(outside.path(), "/evil/escaped")],
+            },
+        );
+        // shared items are mounted by zygote before it handles requests,
+        // it dies if mounting fails
+        if let Ok(sandbox) = &res {
+            minion::Sandbox::expose(sandbox, item(outside.path(), "/synced")).ok();
+        }
+        assert!(!outside.path().join("escaped").exists());
+    }
+
+    fn filter(&self, profile: &str) -> bool {
+        // without new mount API symlinks are followed
+        profile != "prlimit-rootless" && minion::linux::check::new_mount_api_supported()
+    }
+}
+
+pub(crate) struct TSeccompOverride;
+impl crate::TestCase for TSeccompOverride {
+    fn name(&self) -> &'static str {
+        "test_seccomp_override"
+    }
+
+    fn description(&self) -> &'static str {
+        "verifies that per-process seccomp policy is applied"
+    }
+
+    fn test(&self) -> ! {
+        let err = std::fs::read_dir("/").unwrap().next().unwrap().unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(nix::libc::EACCES));
+        exit(0)
+    }
+
+    fn check(&self, mut cp: crate::CompletedChild, _: &dyn Sandbox) {
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode::OK);
+        super::assert_empty(cp.stderr);
+    }
+
+    fn modify_settings(&self, settings: &mut minion::ChildProcessOptions) {
+        use minion::linux::{CommandExt, SeccompAction, SeccompPolicy, SeccompRules, SyscallRule};
+        let mut rules = SeccompRules::new(SeccompAction::Allow);
+        let deny = SeccompAction::Errno(nix::libc::EACCES as u16);
+        rules.add_rule(SyscallRule::new(nix::libc::SYS_getdents64, deny));
+        settings.seccomp(SeccompPolicy::Rules { rules });
+    }
+}
+
+pub(crate) struct TSeccompOverridePure;
+
+impl TSeccompOverridePure {
+    /// Rust programs can not start under pure policy, so sandbox runs this
+    /// static x86_64 program instead. It performs `write(1, NULL, 0)` and
+    /// passes negated result to `exit`.
+    fn program_path() -> std::path::PathBuf {
+        std::env::temp_dir().join("minion-tests-pure-program")
+    }
+
+    fn program() -> Vec<u8> {
+        const BASE: u64 = 0x40_0000;
+        const HEADERS_SIZE: u64 = 64 + 56;
+        let code: &[u8] = &[
+            0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, 1 (write)
+            0xbf, 0x01, 0x00, 0x00, 0x00, // mov edi, 1
+            0x31, 0xf6, // xor esi, esi
+            0x31, 0xd2, // xor edx, edx
+            0x0f, 0x05, // syscall
+            0xf7, 0xd8, // neg eax
+            0x89, 0xc7, // mov edi, eax
+            0xb8, 0x3c, 0x00, 0x00, 0x00, // mov eax, 60 (exit)
+            0x0f, 0x05, // syscall
+        ];
+        let size = HEADERS_SIZE + code.len() as u64;
+        let mut elf = Vec::new();
+        // ELF header
+        elf.extend_from_slice(b"\x7fELF\x02\x01\x01\x00");
+        elf.extend_from_slice(&[0; 8]);
+        elf.extend_from_slice(&2u16.to_le_bytes()); // e_type = ET_EXEC
+        elf.extend_from_slice(&0x3eu16.to_le_bytes()); // e_machine = EM_X86_64
+        elf.extend_from_slice(&1u32.to_le_bytes()); // e_version
+        elf.extend_from_slice(&(BASE + HEADERS_SIZE).to_le_bytes()); // e_entry
+        elf.extend_from_slice(&64u64.to_le_bytes()); // e_phoff
+        elf.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+        elf.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+        elf.extend_from_slice(&64u16.to_le_bytes()); // e_ehsize
+        elf.extend_from_slice(&56u16.to_le_bytes()); // e_phentsize
+        elf.extend_from_slice(&1u16.to_le_bytes()); // e_phnum
+        elf.extend_from_slice(&[0; 6]); // e_shentsize, e_shnum, e_shstrndx
+                                        // program header
+        elf.extend_from_slice(&1u32.to_le_bytes()); // p_type = PT_LOAD
+        elf.extend_from_slice(&5u32.to_le_bytes()); // p_flags = R | X
+        elf.extend_from_slice(&0u64.to_le_bytes()); // p_offset
+        elf.extend_from_slice(&BASE.to_le_bytes()); // p_vaddr
+        elf.extend_from_slice(&BASE.to_le_bytes()); // p_paddr
+        elf.extend_from_slice(&size.to_le_bytes()); // p_filesz
+        elf.extend_from_slice(&size.to_le_bytes()); // p_memsz
+        elf.extend_from_slice(&0x1000u64.to_le_bytes()); // p_align
+        elf.extend_from_slice(code);
+        elf
+    }
+}
+
+impl crate::TestCase for TSeccompOverridePure {
+    fn name(&self) -> &'static str {
+        "test_seccomp_override_pure"
+    }
+
+    fn description(&self) -> &'static str {
+        "verifies that per-process seccomp policy can be stacked \
+        with default policy forbidding seccomp(2)"
+    }
+
+    fn test(&self) -> ! {
+        unreachable!("sandbox runs generated program")
+    }
+
+    fn check(&self, mut cp: crate::CompletedChild, _: &dyn Sandbox) {
+        // write is allowed by the default policy and denied by the override
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode(nix::libc::EACCES.into()));
+        super::assert_empty(cp.stderr);
+    }
+
+    fn shared_items(&self) -> Vec<minion::SharedItem> {
+        use std::os::unix::fs::PermissionsExt;
+        let path = Self::program_path();
+        std::fs::write(&path, Self::program()).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        vec![minion::SharedItem {
+            id: None,
+            src: path,
+            dest: "/pure".into(),
+            kind: minion::SharedItemKind::Readonly,
+            flags: vec![],
+        }]
+    }
+
+    fn modify_settings(&self, settings: &mut minion::ChildProcessOptions) {
+        use minion::linux::{CommandExt, SeccompAction, SeccompPolicy, SeccompRules, SyscallRule};
+        let mut rules = SeccompRules::new(SeccompAction::Allow);
+        let deny = SeccompAction::Errno(nix::libc::EACCES as u16);
+        rules.add_rule(SyscallRule::new(nix::libc::SYS_write, deny));
+        settings.seccomp(SeccompPolicy::Rules { rules });
+        settings.path = "/pure".into();
+    }
+
+    fn modify_backend_settings(&self, settings: &mut minion::linux::Settings) {
+        settings.seccomp = minion::linux::SeccompPolicy::Pure;
+    }
+
+    fn filter(&self, _profile: &str) -> bool {
+        cfg!(target_arch = "x86_64")
+    }
+}
+
+pub(crate) struct TNotifySocketSealed;
+impl crate::TestCase for TNotifySocketSealed {
+    fn name(&self) -> &'static str {
+        "test_notify_socket_sealed"
+    }
+
+    fn description(&self) -> &'static str {
+        "verifies that exceptions needed to send seccomp listener \
+        do not survive exec"
+    }
+
+    fn test(&self) -> ! {
+        // filter is checked before descriptor is resolved, so any number works
+        for fd in 0..1024 {
+            let msg: nix::libc::msghdr = unsafe { std::mem::zeroed() };
+            let ret = unsafe { nix::libc::sendmsg(fd, &msg, 0) };
+            assert_eq!(ret, -1);
+            let err = std::io::Error::last_os_error();
+            assert_eq!(err.raw_os_error(), Some(nix::libc::EPERM), "fd {}", fd);
+        }
+        // seccomp(2) is needed to install the seal, but is denied after it
+        let ret = unsafe { nix::libc::syscall(nix::libc::SYS_seccomp, 0, 0, 0) };
+        assert_eq!(ret, -1);
+        let err = std::io::Error::last_os_error();
+        assert_eq!(err.raw_os_error(), Some(nix::libc::EPERM));
+        exit(0)
+    }
+
+    fn check(&self, mut cp: crate::CompletedChild, _: &dyn Sandbox) {
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode::OK);
+        super::assert_empty(cp.stderr);
+    }
+
+    fn modify_settings(&self, settings: &mut minion::ChildProcessOptions) {
+        use minion::linux::{CommandExt, SeccompAction, SeccompPolicy, SeccompRules, SyscallRule};
+        let mut rules = SeccompRules::new(SeccompAction::Allow);
+        // makes policy report violations, so notification socket is used
+        rules.add_rule(SyscallRule::new(
+            nix::libc::SYS_ptrace,
+            SeccompAction::Report,
+        ));
+        let deny = SeccompAction::Errno(nix::libc::EPERM as u16);
+        rules.add_rule(SyscallRule::new(nix::libc::SYS_sendmsg, deny));
+        rules.add_rule(SyscallRule::new(nix::libc::SYS_seccomp, deny));
+        settings.seccomp(SeccompPolicy::Rules { rules });
+    }
+
+    fn filter(&self, _profile: &str) -> bool {
+        minion::linux::check::seccomp_notify_supported()
+    }
+}
+
+pub(crate) struct TThreadViolation;
+impl crate::TestCase for TThreadViolation {
+    fn name(&self) -> &'static str {
+        "test_thread_violation"
+    }
+
+    fn description(&self) -> &'static str {
+        "checks that violation made by a secondary thread is reported \
+        for the whole process"
+    }
+
+    fn test(&self) -> ! {
+        println!("{}", std::process::id());
+        let thread = std::thread::spawn(|| unsafe {
+            nix::libc::syscall(nix::libc::SYS_getppid);
+        });
+        thread.join().ok();
+        exit(0)
+    }
+
+    fn check(&self, cp: crate::CompletedChild, sb: &dyn Sandbox) {
+        assert_ne!(cp.exit_code, minion::ExitCode::OK);
+        let mut out = String::new();
+        cp.stdout.read_to_string(&mut out).unwrap();
+        let pid: u32 = out.trim().parse().unwrap();
+        let violation = cp.security_violation.expect("violation was not reported");
+        assert_eq!(violation.syscall, nix::libc::SYS_getppid);
+        assert_eq!(violation.pid, pid);
+        assert_eq!(sb.security_violations().unwrap(), vec![violation]);
+    }
+
+    fn process_count_limit(&self) -> u32 {
+        2
+    }
+
+    fn modify_settings(&self, settings: &mut minion::ChildProcessOptions) {
+        use minion::linux::{CommandExt, SeccompAction, SeccompPolicy, SeccompRules, SyscallRule};
+        let mut rules = SeccompRules::new(SeccompAction::Allow);
+        rules.add_rule(SyscallRule::new(
+            nix::libc::SYS_getppid,
+            SeccompAction::Report,
+        ));
+        settings.seccomp(SeccompPolicy::Rules { rules });
+    }
+
+    fn filter(&self, profile: &str) -> bool {
+        // we will not be able to spawn a thread
+        profile != "prlimit-rootless" && minion::linux::check::seccomp_notify_supported()
+    }
+}
+
+pub(crate) struct TDisassemble;
+impl crate::TestCase for TDisassemble {
+    fn name(&self) -> &'static str {
+        "test_disassemble"
+    }
+
+    fn description(&self) -> &'static str {
+        "checks that seccomp policies are disassembled and manual \
+        policies are validated"
+    }
+
+    fn test(&self) -> ! {
+        exit(0)
+    }
+
+    fn check(&self, mut cp: crate::CompletedChild, _: &dyn Sandbox) {
+        use minion::linux::{SeccompAction, SeccompPolicy, SeccompRules, SyscallRule};
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode::OK);
+        let mut rules = SeccompRules::new(SeccompAction::Allow);
+        let deny = SeccompAction::Errno(nix::libc::EACCES as u16);
+        rules.add_rule(SyscallRule::new(nix::libc::SYS_getdents64, deny));
+        let listing = SeccompPolicy::Rules { rules }.disassemble().unwrap();
+        assert!(listing.starts_with("filter 0:\n"), "{}", listing);
+        assert!(listing.contains("/* getdents64 */"), "{}", listing);
+        assert!(listing.contains("return ERRNO(13)"), "{}", listing);
+        assert!(listing.contains("return KILL_PROCESS"), "{}", listing);
+
+        let listing = SeccompPolicy::SingleProcess.disassemble().unwrap();
+        assert!(listing.contains("filter 1:\n"), "{}", listing);
+
+        // no filter is installed
+        let listing = SeccompPolicy::Unrestricted.disassemble().unwrap();
+        assert!(listing.is_empty(), "{}", listing);
+
+        // not a multiple of instruction size
+        let policy = SeccompPolicy::Manual { policy: vec![0; 7] };
+        assert!(policy.disassemble().is_err());
+        // does not end with return
+        let policy = SeccompPolicy::Manual {
+            policy: vec![0x20, 0, 0, 0, 0, 0, 0, 0],
+        };
+        assert!(policy.disassemble().is_err());
+    }
+}
+
+pub(crate) struct TCpuBandwidth;
+impl crate::TestCase for TCpuBandwidth {
+    fn name(&self) -> &'static str {
+        "test_cpu_bandwidth"
+    }
+
+    fn description(&self) -> &'static str {
+        "launches program that does work for some wall-clock time \
+        and checks that it was throttled"
+    }
+
+    fn test(&self) -> ! {
+        let start = std::time::Instant::now();
+        while start.elapsed() < std::time::Duration::from_millis(500) {}
+        let usage = unsafe {
+            let mut usage = std::mem::zeroed();
+            assert_eq!(nix::libc::getrusage(nix::libc::RUSAGE_SELF, &mut usage), 0);
+            usage
+        };
+        let to_duration = |tv: nix::libc::timeval| {
+            std::time::Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000)
+        };
+        let cpu = to_duration(usage.ru_utime) + to_duration(usage.ru_stime);
+        // quarter of a core gives 125ms, one full period is left for slack
+        assert!(cpu < std::time::Duration::from_millis(250), "{:?}", cpu);
+        exit(0)
+    }
+
+    fn check(&self, mut cp: crate::CompletedChild, _: &dyn Sandbox) {
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode::OK);
+        super::assert_empty(cp.stderr);
+    }
+
+    fn cpu_bandwidth(&self) -> Option<minion::CpuBandwidth> {
+        Some(minion::CpuBandwidth::cores(0.25))
+    }
+
+    fn filter(&self, profile: &str) -> bool {
+        // bandwidth can only be limited with cgroups
+        !profile.starts_with("prlimit")
+    }
+}
+
+pub(crate) struct TInvalidOptions;
+impl crate::TestCase for TInvalidOptions {
+    fn name(&self) -> &'static str {
+        "test_invalid_options"
+    }
+
+    fn description(&self) -> &'static str {
+        "checks that sandbox options with out of range values are rejected"
+    }
+
+    fn test(&self) -> ! {
+        exit(0)
+    }
+
+    fn check(&self, mut cp: crate::CompletedChild, _: &dyn Sandbox) {
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode::OK);
+        let root = tempfile::TempDir::new().unwrap();
+        let backend = minion::linux::LinuxBackend::new(crate::worker::backend_settings()).unwrap();
+        let bandwidth = |period| {
+            Some(minion::CpuBandwidth {
+                quota: period,
+                period,
+            })
+        };
+        let short = std::time::Duration::from_micros(500);
+        let long = std::time::Duration::from_secs(2);
+        let cases = [
+            (bandwidth(short), None),
+            (bandwidth(long), None),
+            (None, Some(1 << 27)),
+        ];
+        for (cpu_bandwidth, memory_high) in cases.iter().cloned() {
+            let res = minion::Backend::new_sandbox(
+                &backend,
+                minion::SandboxOptions {
+                    cpu_time_limit: self.time_limit(),
+                    real_time_limit: self.real_time_limit(),
+                    cpu_bandwidth,
+                    cpuset: None,
+                    io_limits: Vec::new(),
+                    max_alive_process_count: 1,
+                    memory_limit: 1 << 26,
+                    memory_high,
+                    swap: minion::SwapPolicy::Deny,
+                    isolation_root: root.path().to_path_buf(),
+                    shared_items: Vec::new(),
+                },
+            );
+            match res {
+                Err(minion::linux::Error::InvalidOptions { .. }) => {}
+                other => panic!(
+                    "options {:?} {:?} were not rejected: {:?}",
+                    cpu_bandwidth, memory_high, other
+                ),
+            }
+        }
+    }
+}
+
+pub(crate) struct TCpuset;
+impl crate::TestCase for TCpuset {
+    fn name(&self) -> &'static str {
+        "test_cpuset"
+    }
+
+    fn description(&self) -> &'static str {
+        "checks that sandbox is pinned to given CPUs"
+    }
+
+    fn test(&self) -> ! {
+        let set = nix::sched::sched_getaffinity(nix::unistd::Pid::from_raw(0)).unwrap();
+        assert!(set.is_set(0).unwrap());
+        for cpu in 1..nix::sched::CpuSet::count() {
+            assert!(!set.is_set(cpu).unwrap(), "{} is set", cpu);
+        }
+        exit(0)
+    }
+
+    fn check(&self, mut cp: crate::CompletedChild, _: &dyn Sandbox) {
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode::OK);
+        super::assert_empty(cp.stderr);
+    }
+
+    fn cpuset(&self) -> Option<minion::Cpuset> {
+        Some(minion::Cpuset {
+            cpus: vec![0],
+            mems: vec![],
+        })
+    }
+}
+
+pub(crate) struct TPidsLimit;
+impl crate::TestCase for TPidsLimit {
+    fn name(&self) -> &'static str {
+        "test_pids_limit_exceeded"
+    }
+
+    fn description(&self) -> &'static str {
+        "checks that failed fork because of process limit is reported"
+    }
+
+    fn test(&self) -> ! {
+        let err = unsafe { nix::unistd::fork() }.unwrap_err();
+        assert_eq!(err.as_errno(), Some(nix::errno::Errno::EAGAIN));
+        exit(0)
+    }
+
+    fn check(&self, mut cp: crate::CompletedChild, sb: &dyn Sandbox) {
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode::OK);
+        super::assert_empty(cp.stderr);
+        assert!(sb.check_pids_limit_exceeded().unwrap());
+    }
+
+    fn filter(&self, profile: &str) -> bool {
+        // prlimit can not detect that limit was hit
+        !profile.starts_with("prlimit")
+    }
+}
+
+pub(crate) struct TSingleProcess;
+impl crate::TestCase for TSingleProcess {
+    fn name(&self) -> &'static str {
+        "test_single_process_policy"
+    }
+
+    fn description(&self) -> &'static str {
+        "checks that single process policy denies fork but allows threads"
+    }
+
+    fn test(&self) -> ! {
+        let err = unsafe { nix::unistd::fork() }.unwrap_err();
+        assert_eq!(err.as_errno(), Some(nix::errno::Errno::EPERM));
+        assert_eq!(std::thread::spawn(|| 42).join().unwrap(), 42);
+        exit(0)
+    }
+
+    fn check(&self, mut cp: crate::CompletedChild, _: &dyn Sandbox) {
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode::OK);
+        super::assert_empty(cp.stderr);
+    }
+
+    fn process_count_limit(&self) -> u32 {
+        2
+    }
+
+    fn modify_settings(&self, settings: &mut minion::ChildProcessOptions) {
+        use minion::linux::CommandExt;
+        settings.seccomp(minion::linux::SeccompPolicy::SingleProcess);
+    }
+
+    fn filter(&self, profile: &str) -> bool {
+        // we will not be able to create a thread
+        profile != "prlimit-rootless"
+    }
+}
+
+pub(crate) struct TRootlessFork;
+impl crate::TestCase for TRootlessFork {
+    fn name(&self) -> &'static str {
+        "test_rootless_fork_denied"
+    }
+
+    fn description(&self) -> &'static str {
+        "checks that process creation is denied when process count \
+        can not be limited otherwise"
+    }
+
+    fn test(&self) -> ! {
+        let err = unsafe { nix::unistd::fork() }.unwrap_err();
+        assert_eq!(err.as_errno(), Some(nix::errno::Errno::EPERM));
+        exit(0)
+    }
+
+    fn check(&self, mut cp: crate::CompletedChild, _: &dyn Sandbox) {
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode::OK);
+        super::assert_empty(cp.stderr);
+    }
+
+    fn filter(&self, profile: &str) -> bool {
+        profile == "prlimit-rootless"
+    }
+}
+
+pub(crate) struct TRootlessCgroupV2;
+impl crate::TestCase for TRootlessCgroupV2 {
+    fn name(&self) -> &'static str {
+        "test_rootless_cgroup_v2"
+    }
+
+    fn description(&self) -> &'static str {
+        "checks that limits are enforced in rootless mode using \
+        delegated cgroup v2 subtree"
+    }
+
+    fn test(&self) -> ! {
+        let err = unsafe { nix::unistd::fork() }.unwrap_err();
+        assert_eq!(err.as_errno(), Some(nix::errno::Errno::EAGAIN));
+        exit(0)
+    }
+
+    fn check(&self, mut cp: crate::CompletedChild, sb: &dyn Sandbox) {
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode::OK);
+        super::assert_empty(cp.stderr);
+        assert!(sb.check_pids_limit_exceeded().unwrap());
+        // backend has moved us out of the delegated cgroup
+        let cgroup = std::fs::read_to_string("/proc/self/cgroup").unwrap();
+        let current = cgroup
+            .lines()
+            .find_map(|line| line.strip_prefix("0::"))
+            .unwrap();
+        assert!(current.ends_with("/minion-host"), "{}", current);
+    }
+
+    fn modify_backend_settings(&self, settings: &mut minion::linux::Settings) {
+        settings.rootless = true;
+        settings.cgroup.delegated = true;
+    }
+
+    fn filter(&self, profile: &str) -> bool {
+        profile == "cgroup-v2"
+    }
+}
+
+pub(crate) struct TStaleCleanup;
+impl crate::TestCase for TStaleCleanup {
+    fn name(&self) -> &'static str {
+        "test_stale_sandbox_cleanup"
+    }
+
+    fn description(&self) -> &'static str {
+        "checks that sandboxes left by crashed process are cleaned up"
+    }
+
+    fn test(&self) -> ! {
+        exit(0)
+    }
+
+    fn check(&self, mut cp: crate::CompletedChild, _: &dyn Sandbox) {
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode::OK);
+        super::assert_empty(cp.stderr);
+        // run another worker, and kill it while its sandbox is alive
+        let victim_name = crate::TestCase::name(&TIdle);
+        let mut victim = std::process::Command::new(std::env::current_exe().unwrap())
+            .env_clear()
+            .env("PROFILE", std::env::var("PROFILE").unwrap())
+            .env(crate::WORKER_ENV_NAME, "1")
+            .env("TEST", victim_name)
+            .stdout(std::process::Stdio::null())
+            // zygote panics when the victim dies
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        let find_sandboxed = || find_test_process(victim_name);
+        let mut sandboxed = None;
+        for _ in 0..500 {
+            sandboxed = find_sandboxed();
+            if sandboxed.is_some() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let sandboxed = sandboxed.expect("sandboxed process was not started");
+        victim.kill().unwrap();
+        victim.wait().unwrap();
+
+        let backend = minion::linux::LinuxBackend::new(crate::worker::backend_settings()).unwrap();
+        // zygote inherits the lock of the sandbox cgroup, and it only exits
+        // after it notices that the victim is dead
+        let mut removed = Vec::new();
+        for _ in 0..500 {
+            removed = backend.cleanup_stale_sandboxes().unwrap();
+            if !removed.is_empty() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(!removed.is_empty());
+        assert_ne!(find_sandboxed(), Some(sandboxed));
+        assert!(backend.cleanup_stale_sandboxes().unwrap().is_empty());
+    }
+
+    fn filter(&self, profile: &str) -> bool {
+        // there is nothing to clean up with prlimit
+        !profile.starts_with("prlimit")
+    }
+}
+
+pub(crate) struct TKill;
+impl crate::TestCase for TKill {
+    fn name(&self) -> &'static str {
+        "test_kill"
+    }
+
+    fn description(&self) -> &'static str {
+        "checks that all sandbox processes are dead when wait_killed returns"
+    }
+
+    fn test(&self) -> ! {
+        if let nix::unistd::ForkResult::Child = unsafe { nix::unistd::fork() }.unwrap() {
+            nix::unistd::sleep(1_000_000_000);
+        }
+        exit(0)
+    }
+
+    fn check(&self, mut cp: crate::CompletedChild, sb: &dyn Sandbox) {
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode::OK);
+        assert!(find_test_process(self.name()).is_some());
+        sb.kill().unwrap();
+        assert!(sb.wait_killed(std::time::Duration::from_secs(1)).unwrap());
+        assert_eq!(find_test_process(self.name()), None);
+        // child held the pipe open until it was killed
+        super::assert_empty(cp.stderr);
+    }
+
+    fn process_count_limit(&self) -> u32 {
+        2
+    }
+
+    fn filter(&self, profile: &str) -> bool {
+        // we will not be able to spawn two processes
+        profile != "prlimit-rootless"
+    }
+}
+
+pub(crate) struct TIoCounters;
+impl crate::TestCase for TIoCounters {
+    fn name(&self) -> &'static str {
+        "test_io_counters"
+    }
+
+    fn description(&self) -> &'static str {
+        "checks that block I/O done by sandboxed program is reported"
+    }
+
+    fn test(&self) -> ! {
+        use std::{
+            io::{Read, Write},
+            os::unix::fs::OpenOptionsExt,
+        };
+        // O_DIRECT buffers must be aligned
+        #[derive(Clone)]
+        #[repr(align(4096))]
+        struct Page([u8; 4096]);
+        // buffered writeback is not always accounted to the writer's
+        // cgroup, so page cache is bypassed
+        let mut pages = vec![Page([1; 4096]); 64];
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .custom_flags(nix::libc::O_DIRECT)
+            .open("/io.bin")
+            .unwrap();
+        for page in &pages {
+            file.write_all(&page.0).unwrap();
+        }
+        file.sync_all().unwrap();
+        drop(file);
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(nix::libc::O_DIRECT)
+            .open("/io.bin")
+            .unwrap();
+        for page in &mut pages {
+            file.read_exact(&mut page.0).unwrap();
+        }
+        exit(0)
+    }
+
+    fn check(&self, mut cp: crate::CompletedChild, sb: &dyn Sandbox) {
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode::OK);
+        super::assert_empty(cp.stderr);
+        let usage = sb.resource_usage().unwrap();
+        assert!(usage.io_write.unwrap() >= 64 * 4096, "{:?}", usage.io_write);
+        assert!(usage.io_read.unwrap() >= 64 * 4096, "{:?}", usage.io_read);
+    }
+
+    fn filter(&self, profile: &str) -> bool {
+        // prlimit does not collect I/O statistics
+        !profile.starts_with("prlimit")
+    }
+}
+
+pub(crate) struct TMemoryPeak;
+impl crate::TestCase for TMemoryPeak {
+    fn name(&self) -> &'static str {
+        "test_memory_peak"
+    }
+
+    fn description(&self) -> &'static str {
+        "checks that peak memory usage is reported in bytes"
+    }
+
+    fn test(&self) -> ! {
+        let mut buf = vec![0_u8; 1 << 20];
+        // touch every page so that it becomes resident
+        for i in (0..buf.len()).step_by(4096) {
+            unsafe { std::ptr::write_volatile(&mut buf[i], 1) };
+        }
+        exit(0)
+    }
+
+    fn check(&self, mut cp: crate::CompletedChild, sb: &dyn Sandbox) {
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode::OK);
+        super::assert_empty(cp.stderr);
+        let peak = sb.resource_usage().unwrap().memory_peak.unwrap();
+        // catches kilobytes reported as bytes or scaled twice
+        assert!(peak >= 1 << 20, "{}", peak);
+        assert!(peak < 64 << 20, "{}", peak);
+    }
+}
+
+pub(crate) struct TCpuTimeSplit;
+impl crate::TestCase for TCpuTimeSplit {
+    fn name(&self) -> &'static str {
+        "test_cpu_time_split"
+    }
+
+    fn description(&self) -> &'static str {
+        "checks that user and system CPU time are reported"
+    }
+
+    fn test(&self) -> ! {
+        let busy = std::time::Duration::from_millis(200);
+        let start = std::time::Instant::now();
+        while start.elapsed() < busy {
+            for _ in 0..1000 {
+                unsafe {
+                    asm!("nop");
+                }
+            }
+        }
+        // getrandom does all the work in kernel mode
+        let mut buf = vec![0_u8; 1 << 16];
+        let start = std::time::Instant::now();
+        while start.elapsed() < busy {
+            unsafe { nix::libc::getrandom(buf.as_mut_ptr().cast(), buf.len(), 0) };
+        }
+        exit(0)
+    }
+
+    fn check(&self, mut cp: crate::CompletedChild, sb: &dyn Sandbox) {
+        super::assert_exit_code(cp.by_ref(), minion::ExitCode::OK);
+        super::assert_empty(cp.stderr);
+        let usage = sb.resource_usage().unwrap();
+        let min = 50_000_000;
+        assert!(usage.user_time.unwrap() >= min, "{:?}", usage);
+        assert!(usage.system_time.unwrap() >= min, "{:?}", usage);
+    }
+}
+
+/// Returns pid of a live process running given test case in a sandbox.
+/// Zombies are not found, because their cmdline is empty.
+fn find_test_process(test_name: &str) -> Option<u32> {
+    let cmdline = format!("/me\0{}\0", test_name).into_bytes();
+    std::fs::read_dir("/proc")
+        .unwrap()
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+        .find(|pid| {
+            std::fs::read(format!("/proc/{}/cmdline", pid))
+                .ok()
+                .as_ref()
+                == Some(&cmdline)
+        })
+}
+
 fn exceed_time_limit() -> ! {
     loop {
         unsafe {
@@ -6,14 +6,8 @@ use std::sync::Arc;
 // 16 mibibytes
 const MEMORY_LIMIT_IN_BYTES: u64 = 4 * (1 << 20);
 
-async fn inner_main(test_cases: &[&'static dyn TestCase]) {
-    let test_case_name = std::env::var("TEST").unwrap();
-    let test_case = test_cases
-        .iter()
-        .copied()
-        .find(|&tc| tc.name() == test_case_name)
-        .unwrap();
-
+/// Returns backend settings for the current profile
+pub(crate) fn backend_settings() -> minion::linux::Settings {
     let profile = std::env::var("PROFILE").unwrap();
 
     let resource_driver = {
@@ -25,12 +19,28 @@ async fn inner_main(test_cases: &[&'static dyn TestCase]) {
         }
     };
 
-    let tempdir = tempfile::TempDir::new().expect("cannot create temporary dir");
     let mut settings = minion::linux::Settings::new();
     settings.resource_drivers = vec![resource_driver];
+    if profile.starts_with("prlimit") {
+        settings.prlimit.rss_watchdog = true;
+    }
     if profile == "prlimit-rootless" {
         settings.rootless = true;
     }
+    settings
+}
+
+async fn inner_main(test_cases: &[&'static dyn TestCase]) {
+    let test_case_name = std::env::var("TEST").unwrap();
+    let test_case = test_cases
+        .iter()
+        .copied()
+        .find(|&tc| tc.name() == test_case_name)
+        .unwrap();
+
+    let tempdir = tempfile::TempDir::new().expect("cannot create temporary dir");
+    let mut settings = backend_settings();
+    test_case.modify_backend_settings(&mut settings);
     {
         let mut res = minion::CheckResult::new();
         minion::linux::check::check(&settings, &mut res);
@@ -39,11 +49,16 @@ async fn inner_main(test_cases: &[&'static dyn TestCase]) {
         }
     }
     let backend = minion::linux::LinuxBackend::new(settings).expect("backend creation failed");
-    let opts = minion::SandboxOptions {
+    let mut opts = minion::SandboxOptions {
         cpu_time_limit: test_case.time_limit(),
         real_time_limit: test_case.real_time_limit(),
+        cpu_bandwidth: test_case.cpu_bandwidth(),
+        cpuset: test_case.cpuset(),
+        io_limits: Vec::new(),
         max_alive_process_count: test_case.process_count_limit(),
         memory_limit: MEMORY_LIMIT_IN_BYTES,
+        memory_high: None,
+        swap: minion::SwapPolicy::Deny,
         isolation_root: tempdir.path().to_path_buf(),
         shared_items: vec![minion::SharedItem {
             id: None,
@@ -53,6 +68,7 @@ async fn inner_main(test_cases: &[&'static dyn TestCase]) {
             flags: vec![],
         }],
     };
+    opts.shared_items.extend(test_case.shared_items());
     let sandbox = backend.new_sandbox(opts).expect("can not create sandbox");
     let sandbox = Arc::new(sandbox);
     let mut opts = minion::ChildProcessOptions {
@@ -66,6 +82,7 @@ async fn inner_main(test_cases: &[&'static dyn TestCase]) {
         },
         extra_inherit: Vec::new(),
         pwd: "/".into(),
+        platform: Default::default(),
     };
     test_case.modify_settings(&mut opts);
     let mut cp = backend
@@ -81,6 +98,11 @@ async fn inner_main(test_cases: &[&'static dyn TestCase]) {
             exit_code,
             stdout: &mut cp.stdout().unwrap(),
             stderr: &mut cp.stderr().unwrap(),
+            security_violation: match cp.security_violation() {
+                // violations can not be queried after watchdog killed the sandbox
+                Err(minion::linux::Error::SandboxGone) => None,
+                res => res.expect("failed to get security violation"),
+            },
         },
         &*sandbox,
     );
@@ -1,5 +1,6 @@
 use crate::{
-    erased, ChildProcessOptions, InputSpecification, OutputSpecification, StdioSpecification,
+    erased, ChildProcessOptions, InputSpecification, OutputSpecification, PlatformOptions,
+    StdioSpecification,
 };
 use std::{
     ffi::{OsStr, OsString},
@@ -18,6 +19,7 @@ pub struct Command {
     stdout: Option<OutputSpecification>,
     stderr: Option<OutputSpecification>,
     current_dir: Option<PathBuf>,
+    pub(crate) platform: PlatformOptions,
 }
 
 impl Command {
@@ -35,6 +37,7 @@ impl Command {
                 stderr: self.stderr.unwrap_or_else(create_default_out_channel),
             },
             pwd: self.current_dir.unwrap_or_else(|| "/".into()),
+            platform: self.platform,
         };
         Some(opts)
     }
@@ -14,9 +14,19 @@ pub trait Sandbox: std::fmt::Debug + Send + Sync + 'static {
     fn id(&self) -> String;
     fn check_cpu_tle(&self) -> anyhow::Result<bool>;
     fn check_real_tle(&self) -> anyhow::Result<bool>;
+    fn check_pids_limit_exceeded(&self) -> anyhow::Result<bool>;
+    fn check_memory_limit_exceeded(&self) -> anyhow::Result<bool>;
     fn kill(&self) -> anyhow::Result<()>;
+    fn wait_killed(&self, timeout: std::time::Duration) -> anyhow::Result<bool>;
     fn resource_usage(&self) -> anyhow::Result<crate::ResourceUsageData>;
     fn debug_info(&self) -> anyhow::Result<serde_json::Value>;
+    fn filesystem_changes(&self) -> anyhow::Result<Vec<crate::FilesystemChange>>;
+    fn expose(&self, item: crate::SharedItem) -> anyhow::Result<()>;
+    fn unexpose(&self, dest: &std::path::Path) -> anyhow::Result<()>;
+    fn write_file(&self, path: &std::path::Path, data: &[u8], mode: u32) -> anyhow::Result<()>;
+    fn read_file(&self, path: &std::path::Path, max_len: u64) -> anyhow::Result<Vec<u8>>;
+    fn security_violations(&self) -> anyhow::Result<Vec<crate::SecurityViolation>>;
+    fn used_syscalls(&self) -> anyhow::Result<std::collections::BTreeSet<i64>>;
     fn into_arc_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync + 'static>;
 }
 
@@ -30,15 +40,45 @@ impl<S: crate::Sandbox> Sandbox for S {
     fn check_real_tle(&self) -> anyhow::Result<bool> {
         self.check_real_tle().map_err(Into::into)
     }
+    fn check_pids_limit_exceeded(&self) -> anyhow::Result<bool> {
+        self.check_pids_limit_exceeded().map_err(Into::into)
+    }
+    fn check_memory_limit_exceeded(&self) -> anyhow::Result<bool> {
+        self.check_memory_limit_exceeded().map_err(Into::into)
+    }
     fn kill(&self) -> anyhow::Result<()> {
         self.kill().map_err(Into::into)
     }
+    fn wait_killed(&self, timeout: std::time::Duration) -> anyhow::Result<bool> {
+        self.wait_killed(timeout).map_err(Into::into)
+    }
     fn resource_usage(&self) -> anyhow::Result<crate::ResourceUsageData> {
         self.resource_usage().map_err(Into::into)
     }
     fn debug_info(&self) -> anyhow::Result<serde_json::Value> {
         self.debug_info().map_err(Into::into)
     }
+    fn filesystem_changes(&self) -> anyhow::Result<Vec<crate::FilesystemChange>> {
+        self.filesystem_changes().map_err(Into::into)
+    }
+    fn expose(&self, item: crate::SharedItem) -> anyhow::Result<()> {
+        self.expose(item).map_err(Into::into)
+    }
+    fn unexpose(&self, dest: &std::path::Path) -> anyhow::Result<()> {
+        self.unexpose(dest).map_err(Into::into)
+    }
+    fn write_file(&self, path: &std::path::Path, data: &[u8], mode: u32) -> anyhow::Result<()> {
+        self.write_file(path, data, mode).map_err(Into::into)
+    }
+    fn read_file(&self, path: &std::path::Path, max_len: u64) -> anyhow::Result<Vec<u8>> {
+        self.read_file(path, max_len).map_err(Into::into)
+    }
+    fn security_violations(&self) -> anyhow::Result<Vec<crate::SecurityViolation>> {
+        self.security_violations().map_err(Into::into)
+    }
+    fn used_syscalls(&self) -> anyhow::Result<std::collections::BTreeSet<i64>> {
+        self.used_syscalls().map_err(Into::into)
+    }
     fn into_arc_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync + 'static> {
         self
     }
@@ -52,6 +92,7 @@ pub trait ChildProcess: Send + Sync + 'static {
     fn wait_for_exit(
         &mut self,
     ) -> anyhow::Result<futures_util::future::BoxFuture<'static, anyhow::Result<crate::ExitCode>>>;
+    fn security_violation(&self) -> anyhow::Result<Option<crate::SecurityViolation>>;
 }
 
 impl<C: crate::ChildProcess> ChildProcess for C {
@@ -73,6 +114,9 @@ impl<C: crate::ChildProcess> ChildProcess for C {
     {
         Ok(self.wait_for_exit()?.map_err(Into::into).boxed())
     }
+    fn security_violation(&self) -> anyhow::Result<Option<crate::SecurityViolation>> {
+        self.security_violation().map_err(Into::into)
+    }
 }
 
 /// Type-erased `Backend`
@@ -22,6 +22,7 @@ use serde::{Deserialize, Serialize};
 pub use crate::linux::{LinuxBackend, LinuxChildProcess, LinuxSandbox};
 
 use std::{
+    collections::BTreeSet,
     error::Error as StdError,
     fmt::Debug,
     io::{Read, Write},
@@ -73,6 +74,15 @@ pub struct SharedItem {
     pub flags: Vec<String>,
 }
 
+/// Part of `ResourceUsageData`
+#[derive(Debug, Copy, Clone, Default)]
+pub struct MemoryBreakdown {
+    /// Anonymous memory (heap, stack, etc.), in bytes
+    pub anon: u64,
+    /// Page cache (including tmpfs files), in bytes
+    pub file: u64,
+}
+
 /// This struct is returned by `Sandbox::resource_usage`
 /// It represents various resource usage
 /// Some items can be absent or rounded
@@ -80,8 +90,155 @@ pub struct SharedItem {
 pub struct ResourceUsageData {
     /// Total CPU time usage in nanoseconds
     pub time: Option<u64>,
-    /// Max memory usage in bytes
-    pub memory: Option<u64>,
+    /// CPU time spent in user mode, in nanoseconds
+    pub user_time: Option<u64>,
+    /// CPU time spent in kernel mode, in nanoseconds
+    pub system_time: Option<u64>,
+    /// Wall-clock time since sandbox creation, in nanoseconds
+    pub wall_time: Option<u64>,
+    /// Max memory usage in bytes. With prlimit driver, it is the largest
+    /// peak RSS of a single process.
+    pub memory_peak: Option<u64>,
+    /// Memory usage in bytes at the moment of the call
+    pub memory_current: Option<u64>,
+    /// What current memory usage consists of
+    pub memory_breakdown: Option<MemoryBreakdown>,
+    /// Total bytes read from block devices
+    pub io_read: Option<u64>,
+    /// Total bytes written to block devices
+    pub io_write: Option<u64>,
+    /// Page faults which required I/O
+    pub major_page_faults: Option<u64>,
+    /// Page faults which were handled without I/O
+    pub minor_page_faults: Option<u64>,
+    /// Context switches because process waited for a resource
+    pub voluntary_context_switches: Option<u64>,
+    /// Context switches because time slice was over or because of
+    /// higher-priority process
+    pub involuntary_context_switches: Option<u64>,
+    /// Max number of processes alive at the same time
+    pub pids_peak: Option<u64>,
+}
+
+/// Describes how filesystem entry was changed
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FilesystemChangeKind {
+    /// Entry did not exist when sandbox was created
+    Added,
+    /// Entry existed, but its contents or metadata differ
+    Modified,
+    /// Entry existed when sandbox was created, but does not exist now
+    Removed,
+}
+
+/// This struct is returned by `Sandbox::filesystem_changes`
+/// It represents single created, modified or removed filesystem entry
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FilesystemChange {
+    /// Path for child (i.e. relative to sandbox root)
+    pub path: PathBuf,
+    pub kind: FilesystemChangeKind,
+    /// Size in bytes when sandbox was created. None for added entries
+    pub old_size: Option<u64>,
+    /// Current size in bytes. None for removed entries
+    pub new_size: Option<u64>,
+}
+
+/// This struct is returned by `Sandbox::security_violations`
+/// It represents syscall forbidden by the sandbox policy
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct SecurityViolation {
+    /// Process that made the syscall (as seen inside the sandbox)
+    pub pid: u32,
+    pub syscall: i64,
+    /// None if syscall is unknown
+    pub syscall_name: Option<String>,
+    pub args: [u64; 6],
+}
+
+impl std::fmt::Display for SecurityViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "security violation: syscall {} ({}) with args {:x?}",
+            self.syscall,
+            self.syscall_name.as_deref().unwrap_or("unknown"),
+            self.args
+        )
+    }
+}
+
+/// Limits CPU bandwidth of the sandbox: all its processes together
+/// can use at most `quota` of CPU time in each `period` of wall-clock time.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CpuBandwidth {
+    /// Must be at least 1ms
+    pub quota: Duration,
+    /// Must be between 1ms and 1s
+    pub period: Duration,
+}
+
+impl CpuBandwidth {
+    /// Default period used by the kernel
+    pub const DEFAULT_PERIOD: Duration = Duration::from_millis(100);
+
+    /// Allows using `cores` CPUs (can be fractional, e.g. 0.5).
+    pub fn cores(cores: f64) -> CpuBandwidth {
+        CpuBandwidth {
+            quota: Self::DEFAULT_PERIOD.mul_f64(cores),
+            period: Self::DEFAULT_PERIOD,
+        }
+    }
+}
+
+/// Throttles I/O on a block device. Limits which are None are not applied.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq)]
+pub struct IoLimit {
+    /// Block device, e.g. `/dev/sda`
+    pub device: PathBuf,
+    /// Bytes per second
+    pub read_bps: Option<u64>,
+    /// Bytes per second
+    pub write_bps: Option<u64>,
+    /// Operations per second
+    pub read_iops: Option<u64>,
+    /// Operations per second
+    pub write_iops: Option<u64>,
+}
+
+/// Controls how sandbox can use swap.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SwapPolicy {
+    /// Sandbox memory is never swapped out.
+    ///
+    /// This is best-effort: if swap accounting is disabled on the host
+    /// (so `memory.swap.max` or `memory.memsw.limit_in_bytes` does not
+    /// exist), swap usage can not be restricted and a warning is logged.
+    /// On cgroup v1 `memory.swappiness` is set to zero anyway.
+    ///
+    /// This is the default.
+    Deny,
+    /// Sandbox can additionally use given number of bytes of swap.
+    /// Sandbox creation fails if swap accounting is disabled or if
+    /// resource limits are not enforced with cgroups.
+    Limit(u64),
+    /// Swap usage is not restricted
+    Unrestricted,
+}
+
+impl Default for SwapPolicy {
+    fn default() -> Self {
+        SwapPolicy::Deny
+    }
+}
+
+/// Restricts sandbox to given CPUs and memory nodes.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq)]
+pub struct Cpuset {
+    /// CPU numbers. Empty list means all CPUs.
+    pub cpus: Vec<u32>,
+    /// NUMA memory nodes. Empty list means all nodes.
+    pub mems: Vec<u32>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -89,10 +246,27 @@ pub struct SandboxOptions {
     pub max_alive_process_count: u32,
     /// Memory limit for all processes in cgroup, in bytes
     pub memory_limit: u64,
+    /// Memory usage (in bytes) above which processes are throttled and
+    /// their memory is reclaimed. Must not exceed `memory_limit`,
+    /// otherwise sandbox creation fails.
+    #[serde(default)]
+    pub memory_high: Option<u64>,
+    #[serde(default)]
+    pub swap: SwapPolicy,
     /// Specifies total CPU time for whole sandbox
     pub cpu_time_limit: Duration,
     /// Specifies total wall-clock timer limit for whole sandbox
     pub real_time_limit: Duration,
+    /// Limits how many CPUs sandbox can use at once. None means unlimited.
+    #[serde(default)]
+    pub cpu_bandwidth: Option<CpuBandwidth>,
+    /// Pins sandbox to given CPUs. If None, backend can choose CPUs
+    /// itself (see backend docs).
+    #[serde(default)]
+    pub cpuset: Option<Cpuset>,
+    /// Per-device disk I/O limits
+    #[serde(default)]
+    pub io_limits: Vec<IoLimit>,
     pub isolation_root: PathBuf,
     pub shared_items: Vec<SharedItem>,
 }
@@ -126,16 +300,68 @@ pub trait Sandbox: Debug + Send + Sync + 'static {
     /// Returns true if sandbox exceeded wall-clock time limit
     fn check_real_tle(&self) -> Result<bool, Self::Error>;
 
-    /// Kills all processes in sandbox.
+    /// Returns true if sandbox tried to create more processes than allowed
+    /// by `max_alive_process_count`. Backends which can not detect it
+    /// always return false.
+    fn check_pids_limit_exceeded(&self) -> Result<bool, Self::Error>;
+
+    /// Returns true if sandbox processes were killed because sandbox
+    /// exceeded `memory_limit`. Backends which can not detect it always
+    /// return false.
+    fn check_memory_limit_exceeded(&self) -> Result<bool, Self::Error>;
+
+    /// Sends SIGKILL to all processes in sandbox. Does not wait until
+    /// they exit, use `wait_killed` for that.
     /// Probably, subsequent `spawn` requests will fail.
     fn kill(&self) -> Result<(), Self::Error>;
 
+    /// Waits at most `timeout` until all processes of the killed sandbox
+    /// exit (if backend can track them, e.g. using cgroups). Returns false
+    /// on timeout. Must only be called after `kill`: processes which are
+    /// still running are killed.
+    fn wait_killed(&self, timeout: Duration) -> Result<bool, Self::Error>;
+
     /// Returns debugging information, such as pathes or process
     /// identifiers.
     fn debug_info(&self) -> Result<serde_json::Value, Self::Error>;
 
     /// Returns information about resource usage by total sandbox
     fn resource_usage(&self) -> Result<ResourceUsageData, Self::Error>;
+
+    /// Returns entries that were created, modified or removed in writable
+    /// areas of the sandbox since it was created.
+    fn filesystem_changes(&self) -> Result<Vec<FilesystemChange>, Self::Error>;
+
+    /// Makes `item` available to the sandbox. Unlike items from
+    /// `SandboxOptions`, it is visible to already running processes too.
+    /// On Linux, this requires the new mount API and is not supported in
+    /// rootless mode.
+    fn expose(&self, item: SharedItem) -> Result<(), Self::Error>;
+
+    /// Removes item previously exposed at `dest` (path for child) by
+    /// `expose`. Items from `SandboxOptions` can not be removed.
+    /// Processes that use files from this item can keep using them.
+    fn unexpose(&self, dest: &Path) -> Result<(), Self::Error>;
+
+    /// Replaces contents of file at `path` (path for child) with `data`.
+    /// File is created if it does not exist (but its parent directory
+    /// must exist) and gets permissions `mode` (setuid, setgid and sticky
+    /// bits are ignored). Sandboxed processes will see the file as owned
+    /// by them.
+    /// Symlinks are never followed.
+    fn write_file(&self, path: &Path, data: &[u8], mode: u32) -> Result<(), Self::Error>;
+
+    /// Reads at most `max_len` bytes from file at `path` (path for child).
+    /// Symlinks are never followed.
+    fn read_file(&self, path: &Path, max_len: u64) -> Result<Vec<u8>, Self::Error>;
+
+    /// Returns syscalls that were forbidden by the sandbox policy and
+    /// reported. Only some policies report violations.
+    fn security_violations(&self) -> Result<Vec<SecurityViolation>, Self::Error>;
+
+    /// Returns numbers of all syscalls made by sandboxed processes.
+    /// Only some policies record syscalls, otherwise empty set is returned.
+    fn used_syscalls(&self) -> Result<BTreeSet<i64>, Self::Error>;
 }
 
 /// Kernel object descriptor.
@@ -246,6 +472,16 @@ pub struct ChildProcessOptions {
     pub extra_inherit: Vec<Handle>,
     /// Child's working dir. Relative to `sandbox` isolation_root
     pub pwd: PathBuf,
+    /// Platform-specific options, see `linux::CommandExt`
+    pub platform: PlatformOptions,
+}
+
+/// Options that are only supported on some platforms.
+/// They are set with platform-specific extension traits.
+#[derive(Debug, Default)]
+pub struct PlatformOptions {
+    #[cfg(target_os = "linux")]
+    pub(crate) seccomp: Option<crate::linux::SeccompPolicy>,
 }
 
 use std::{
@@ -319,4 +555,8 @@ pub trait ChildProcess: Debug + Send + Sync + 'static {
     ///
     /// On all subsequent calls, None will be returned
     fn stderr(&mut self) -> Option<Self::PipeOut>;
+
+    /// Returns reported security violation, if process was killed
+    /// because of it.
+    fn security_violation(&self) -> Result<Option<SecurityViolation>, Self::Error>;
 }
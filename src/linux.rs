@@ -1,4 +1,5 @@
 pub mod check;
+mod cpu_alloc;
 pub mod error;
 pub mod ext;
 mod fd;
@@ -25,6 +26,10 @@ use crate::{
 pub use error::Error;
 use nix::sys::memfd;
 pub use sandbox::LinuxSandbox;
+pub use seccomp::{
+    rules::{ArgCondition, ArgOp, SeccompAction, SeccompRules, SyscallRule},
+    PolicyError, PredefinedPolicy,
+};
 use std::{
     ffi::CString,
     fs,
@@ -36,7 +41,7 @@ use std::{
     },
 };
 
-use self::uid_alloc::UidAllocator;
+use self::{cpu_alloc::CpuAllocator, uid_alloc::UidAllocator};
 
 pub type LinuxHandle = libc::c_int;
 pub struct LinuxChildProcess {
@@ -91,6 +96,11 @@ impl ChildProcess for LinuxChildProcess {
             self.sandbox_ref.clone(),
         )
     }
+
+    fn security_violation(&self) -> Result<Option<crate::SecurityViolation>, Error> {
+        let violations = self.sandbox_ref.task_security_violations(Some(self.pid))?;
+        Ok(violations.into_iter().next())
+    }
 }
 
 fn handle_input_io(
@@ -157,6 +167,29 @@ fn handle_output_io(
     }
 }
 
+/// Linux-specific extensions to `Command` and `ChildProcessOptions`
+pub trait CommandExt {
+    /// Sets additional seccomp policy for the process. It is applied
+    /// together with the sandbox policy, so the process can only be
+    /// restricted further. At most one of these policies may forbid
+    /// seccomp(2), otherwise spawn fails.
+    fn seccomp(&mut self, policy: SeccompPolicy) -> &mut Self;
+}
+
+impl CommandExt for crate::Command {
+    fn seccomp(&mut self, policy: SeccompPolicy) -> &mut Self {
+        self.platform.seccomp.replace(policy);
+        self
+    }
+}
+
+impl CommandExt for ChildProcessOptions {
+    fn seccomp(&mut self, policy: SeccompPolicy) -> &mut Self {
+        self.platform.seccomp.replace(policy);
+        self
+    }
+}
+
 fn spawn(
     mut options: ChildProcessOptions,
     sandbox: Arc<LinuxSandbox>,
@@ -170,6 +203,12 @@ fn spawn(
                 .collect(),
             pwd: options.pwd.clone(),
             extra_fds: options.extra_inherit.iter().map(|h| h.0 as i32).collect(),
+            seccomp: options
+                .platform
+                .seccomp
+                .as_ref()
+                .map(|policy| sandbox.job_seccomp(policy))
+                .transpose()?,
         };
 
         let (in_w, in_r) = handle_input_io(options.stdio.stdin)?;
@@ -224,13 +263,29 @@ impl Default for UserIdBounds {
 pub struct CgroupSettings {
     /// All created cgroups will be children of specified group
     /// Default value is "/minion"
+    /// If `delegated` is set, it is relative to the delegated cgroup.
     pub name_prefix: PathBuf,
 
     /// Overrides path to cgroupfs mount.
     /// This can be both cgroupfs v1 and cgroupfs v2.
     /// Additionally fallback (`/sys/fs/cgroup`) can be overrided
     /// at runtime using `MINION_CGROUPFS` environment variable.
+    ///
+    /// For cgroup v1, controller mount points are found in
+    /// `/proc/self/mountinfo`, so they can be co-mounted (e.g. `cpu,cpuacct`)
+    /// or mounted elsewhere; mount points inside this path are preferred.
+    /// `<mount>/<controller>` is only assumed if procfs is not available.
     pub mount: PathBuf,
+
+    /// Create cgroups in the cgroup of the current process, which must be
+    /// delegated to the current user (e.g. systemd unit with
+    /// `Delegate=yes`). This is needed to use cgroup v2 in rootless mode.
+    ///
+    /// When cgroup v2 driver is selected, current process is moved to
+    /// child cgroup `minion-host`, because cgroups with enabled controllers
+    /// can not have processes. Only used by cgroup v2 driver.
+    /// Default value is false.
+    pub delegated: bool,
 }
 
 impl Default for CgroupSettings {
@@ -240,10 +295,43 @@ impl Default for CgroupSettings {
             mount: std::env::var_os("MINION_CGROUPFS")
                 .map(PathBuf::from)
                 .unwrap_or_else(|| PathBuf::from("/sys/fs/cgroup")),
+            delegated: false,
         }
     }
 }
 
+/// Resource limit which is used by prlimit driver to limit memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryRlimit {
+    /// `RLIMIT_DATA`: limits private writable mappings (heap, anonymous
+    /// memory), but not shared or file-backed ones.
+    /// This is the default.
+    Data,
+    /// `RLIMIT_AS`: limits all mappings. Runtimes which reserve large
+    /// address space upfront (e.g. JVM, Go) can fail to start with it.
+    AddressSpace,
+}
+
+impl Default for MemoryRlimit {
+    fn default() -> Self {
+        MemoryRlimit::Data
+    }
+}
+
+/// Prlimit driver settings
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct PrlimitSettings {
+    /// Limit which is set to `SandboxOptions::memory_limit`.
+    /// Note that it applies to each process separately.
+    pub memory_rlimit: MemoryRlimit,
+    /// If enabled, watchdog sums resident memory of all sandbox processes
+    /// (once a second) and kills the sandbox if it exceeds
+    /// `SandboxOptions::memory_limit`. Shared pages are divided between
+    /// processes which map them (see `Pss` in proc(5)).
+    pub rss_watchdog: bool,
+}
+
 /// Resource limiting implementation
 #[non_exhaustive]
 #[derive(Debug, Clone)]
@@ -258,6 +346,11 @@ pub enum ResourceDriverKind {
     /// This is the only option that does not require write access
     /// to cgroupfs.
     /// # Caveats
+    /// - `max_alive_process_count` limits count of threads too.
+    /// - In rootless mode, sandboxed processes can not create other processes
+    ///   (see `SeccompPolicy::SingleProcess`).
+    /// - Memory limit only applies to some kinds of memory, unless
+    ///   `PrlimitSettings::rss_watchdog` is enabled (see `PrlimitSettings`).
     /// [TODO]
     Prlimit,
     /// Auto-detect
@@ -267,23 +360,52 @@ pub enum ResourceDriverKind {
 #[non_exhaustive]
 #[derive(Debug, Clone)]
 pub enum SeccompPolicy {
-    /// Policy that allows all syscalls is be used.
+    /// No filter is installed, so all syscalls are allowed.
+    /// This policy is supported on all architectures.
     Unrestricted,
     /// Policy that disallows several possibly dangerous syscalls
     /// is used. You should use this (or more restrictive policy)
     /// when `rootless` is false.
     ///
-    /// This is the default.
+    /// This is the default on architectures which have pregenerated
+    /// filters (x86_64, x86 and aarch64). On other architectures the
+    /// default is `Unrestricted`.
     DenyDangerous,
     /// Policy that disallows almost all syscalls
     Pure,
+    /// Same as `DenyDangerous`, but also disallows creation of
+    /// processes (threads can still be created), restricts `ioctl`
+    /// requests to terminal queries and `socket` to `AF_UNIX`.
+    ///
+    /// Process restrictions are added to any policy automatically when
+    /// `ResourceDriverKind::Prlimit` is used in rootless mode, because
+    /// process count can not be limited otherwise.
+    SingleProcess,
+    /// Policy generated by `minion-codegen` from a definition in
+    /// `minion-codegen/policies`.
+    Predefined(PredefinedPolicy),
     /// Use provided policy
     Manual { policy: Vec<u8> },
+    /// Policy compiled from the given rules when sandbox is created.
+    /// Use `SeccompAction::Report` to see which syscalls were denied
+    /// in `Sandbox::security_violations`.
+    Rules { rules: SeccompRules },
+    /// Allows everything, but records all syscalls sandboxed processes
+    /// make (see `Sandbox::used_syscalls`). This is useful to find out
+    /// which syscalls should be allowed for a program.
+    ///
+    /// Every syscall is handled by the sandbox supervisor, so programs
+    /// run much slower. Requires Linux 5.5.
+    Audit,
 }
 
 impl Default for SeccompPolicy {
     fn default() -> Self {
-        SeccompPolicy::DenyDangerous
+        if seccomp::pregenerated_supported() {
+            SeccompPolicy::DenyDangerous
+        } else {
+            SeccompPolicy::Unrestricted
+        }
     }
 }
 
@@ -296,6 +418,8 @@ pub struct Settings {
     pub allow_unsupported_mount_namespace: bool,
     /// Cgroup settings
     pub cgroup: CgroupSettings,
+    /// Prlimit driver settings
+    pub prlimit: PrlimitSettings,
     /// Resource limits drivers to use
     pub resource_drivers: Vec<ResourceDriverKind>,
     /// Do not perform actions that require root access.
@@ -303,6 +427,9 @@ pub struct Settings {
     ///
     /// When set to true, `seccomp` should not be set to
     /// Unrestricted.
+    ///
+    /// In rootless mode, cgroup v2 driver can only be used with
+    /// `CgroupSettings::delegated`.
     pub rootless: bool,
     /// User identifiers to use for the sandboxes.
     /// Ignored in rootless mode (because calling process uid will be used
@@ -310,6 +437,13 @@ pub struct Settings {
     pub uid: UserIdBounds,
     /// Seccomp settings
     pub seccomp: SeccompPolicy,
+    /// CPUs which are handed out to sandboxes created without `cpuset`.
+    /// Each such sandbox is pinned to a dedicated CPU from this list,
+    /// and sandbox creation fails if all of them are in use.
+    /// Empty list (default) disables this.
+    pub cpu_pool: Vec<u32>,
+    /// Call `LinuxBackend::cleanup_stale_sandboxes` when backend is created.
+    pub cleanup_stale_sandboxes: bool,
 }
 
 impl Default for Settings {
@@ -318,12 +452,15 @@ impl Default for Settings {
         Settings {
             allow_unsupported_mount_namespace: false,
             cgroup: CgroupSettings::default(),
+            prlimit: PrlimitSettings::default(),
             resource_drivers: vec![ResourceDriverKind::Auto {
                 allow_dangerous: false,
             }],
             rootless: !have_root,
             uid: Default::default(),
             seccomp: SeccompPolicy::default(),
+            cpu_pool: Vec::new(),
+            cleanup_stale_sandboxes: false,
         }
     }
 }
@@ -338,12 +475,24 @@ impl Settings {
 /// # Sandbox extensions
 /// Supported shared items flags:
 ///  - `recursive`: use recursive bind mounts (i.e. MS_REC)
+///  - `noexec`, `nosuid`, `nodev`, `noatime`: apply corresponding mount flags
+///  - `private`, `slave`: change mount propagation type (at most one of them
+///    can be used)
+///
+/// Flags which are already applied to the source mount (e.g. `nosuid`) are
+/// preserved, because they can not be cleared in a user namespace.
+///
+/// Sandboxes created without `cpuset` are pinned to dedicated CPUs if
+/// `Settings::cpu_pool` is not empty. Prlimit driver applies `cpuset` as
+/// CPU affinity, which can be changed by the sandboxed process.
 #[derive(Debug)]
 pub struct LinuxBackend {
     settings: Settings,
     driver: Arc<limits::Driver>,
     // used for allocating sandbox UIDs when we are root
     uid_alloc: Arc<UidAllocator>,
+    // used for pinning sandboxes to dedicated CPUs
+    cpu_alloc: Arc<CpuAllocator>,
 }
 
 impl Backend for LinuxBackend {
@@ -357,6 +506,7 @@ impl Backend for LinuxBackend {
             &self.settings,
             self.driver.clone(),
             self.uid_alloc.clone(),
+            self.cpu_alloc.clone(),
         )?;
         Ok(sb)
     }
@@ -373,12 +523,37 @@ impl Backend for LinuxBackend {
 impl LinuxBackend {
     pub fn new(settings: Settings) -> Result<LinuxBackend, Error> {
         self::check::run_all_feature_checks();
+        // fail early if policy is invalid or not supported
+        seccomp::Seccomp::new(&settings.seccomp)?;
         let driver = Arc::new(limits::Driver::new(&settings)?);
         let uid_alloc = Arc::new(UidAllocator::new(settings.uid.low, settings.uid.high));
-        Ok(LinuxBackend {
+        let cpu_alloc = Arc::new(CpuAllocator::new(settings.cpu_pool.clone()));
+        let backend = LinuxBackend {
             settings,
             driver,
             uid_alloc,
-        })
+            cpu_alloc,
+        };
+        if backend.settings.cleanup_stale_sandboxes {
+            backend.cleanup_stale_sandboxes()?;
+        }
+        Ok(backend)
+    }
+
+    /// Kills processes and removes cgroups of sandboxes which were not
+    /// dropped properly, e.g. because the process which created them
+    /// crashed. Sandboxes of live backends, including ones in other
+    /// processes, are not affected: backend holds a `flock` on the cgroup
+    /// of each sandbox it created until the sandbox is dropped.
+    /// Returns ids of removed sandboxes.
+    ///
+    /// Only sandboxes with the same `CgroupSettings::name_prefix` are
+    /// considered. Does nothing if prlimit driver is used.
+    pub fn cleanup_stale_sandboxes(&self) -> Result<Vec<String>, Error> {
+        let removed = self.driver.cleanup_stale_groups()?;
+        if !removed.is_empty() {
+            tracing::info!(sandboxes=?removed, "Removed stale sandboxes");
+        }
+        Ok(removed)
     }
 }
@@ -13,6 +13,12 @@ pub fn check(settings: &crate::linux::Settings, res: &mut crate::check::CheckRes
             anyhow::Error::new(err)
         ));
     }
+    for (name, purpose) in crate::linux::limits::missing_v1_controllers(settings) {
+        res.warning(&format!(
+            "cgroup v1 controller `{}` is not mounted, so cgroup v1 driver does not support {}",
+            name, purpose
+        ));
+    }
     if !settings.rootless {
         check_uid(settings, res);
     }
@@ -111,6 +117,34 @@ pub fn pidfd_supported() -> bool {
     *ONCE
 }
 
+/// Checks if the kernel has support for `openat2` and the new mount API.
+/// When it is not supported, shared items are mounted using legacy mount(2),
+/// which follows symlinks in bind target path.
+pub fn new_mount_api_supported() -> bool {
+    static ONCE: once_cell::sync::Lazy<bool> =
+        once_cell::sync::Lazy::new(crate::linux::zygote::mount_api::probe);
+    *ONCE
+}
+
+/// Checks if the kernel supports `SECCOMP_RET_USER_NOTIF`, which is
+/// required for `SeccompAction::Report`.
+pub fn seccomp_notify_supported() -> bool {
+    static ONCE: once_cell::sync::Lazy<bool> =
+        once_cell::sync::Lazy::new(crate::linux::seccomp::notify::probe);
+    *ONCE
+}
+
+/// Checks if the kernel can resume syscalls after notification,
+/// which is required for `SeccompPolicy::Audit`.
+pub fn seccomp_audit_supported() -> bool {
+    static ONCE: once_cell::sync::Lazy<bool> =
+        once_cell::sync::Lazy::new(crate::linux::seccomp::notify::probe_continue);
+    *ONCE
+}
+
 pub(crate) fn run_all_feature_checks() {
     let _ = pidfd_supported();
+    let _ = new_mount_api_supported();
+    let _ = seccomp_notify_supported();
+    let _ = seccomp_audit_supported();
 }
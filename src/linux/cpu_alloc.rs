@@ -0,0 +1,38 @@
+use std::collections::HashSet;
+
+use parking_lot::Mutex;
+
+/// Hands out dedicated CPUs from `Settings::cpu_pool` to sandboxes.
+#[derive(Debug)]
+pub(in crate::linux) struct CpuAllocator {
+    cpus: Vec<u32>,
+    used: Mutex<HashSet<u32>>,
+}
+
+impl CpuAllocator {
+    pub(in crate::linux) fn new(cpus: Vec<u32>) -> Self {
+        CpuAllocator {
+            cpus,
+            used: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub(in crate::linux) fn is_enabled(&self) -> bool {
+        !self.cpus.is_empty()
+    }
+
+    pub(in crate::linux) fn allocate(&self) -> Option<u32> {
+        let mut used = self.used.lock();
+        for &cpu in &self.cpus {
+            if used.insert(cpu) {
+                return Some(cpu);
+            }
+        }
+        None
+    }
+
+    pub(in crate::linux) fn deallocate(&self, cpu: u32) {
+        let mut used = self.used.lock();
+        assert!(used.remove(&cpu));
+    }
+}
@@ -1,6 +1,7 @@
 use crate::linux::{
     ipc::IpcError,
     limits::{DriverError, DriverInitializationError},
+    seccomp::PolicyError,
 };
 
 #[derive(Eq, PartialEq)]
@@ -47,10 +48,23 @@ pub enum Error {
     },
     #[error("Invalid flag used in SharedItem: {flag}")]
     InvalidSharedItemFlag { flag: String },
+    #[error("invalid sandbox options: {message}")]
+    InvalidOptions { message: String },
     #[error("uid range exhausted")]
     UidExhausted,
+    #[error("all CPUs from the pool are in use")]
+    CpusExhausted,
     #[error("sandbox was destroyed")]
     SandboxGone,
+    #[error("sandbox request failed: {message}")]
+    RequestFailed { message: String },
+    #[error("invalid seccomp policy")]
+    InvalidSeccompPolicy {
+        #[from]
+        cause: PolicyError,
+    },
+    #[error("filesystem tree is too large or too deep to track changes")]
+    FilesystemTooLarge,
 }
 
 impl Error {
@@ -63,8 +77,13 @@ impl Error {
             Error::Unknown => ErrorKind::System,
             Error::SandboxIpc { .. } => ErrorKind::Sandbox,
             Error::InvalidSharedItemFlag { .. } => ErrorKind::System,
+            Error::InvalidOptions { .. } => ErrorKind::System,
             Error::UidExhausted => ErrorKind::System,
+            Error::CpusExhausted => ErrorKind::System,
             Error::SandboxGone => ErrorKind::System,
+            Error::RequestFailed { .. } => ErrorKind::System,
+            Error::InvalidSeccompPolicy { .. } => ErrorKind::System,
+            Error::FilesystemTooLarge => ErrorKind::Sandbox,
             Error::ResourceLimits { .. } => ErrorKind::System,
             Error::SelectDriverImpl { .. } => ErrorKind::System,
         }
@@ -32,9 +32,21 @@ pub(crate) struct LinuxSharedItem {
     pub(crate) flags: SharedItemFlags,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub(crate) struct SharedItemFlags {
     pub(crate) recursive: bool,
+    pub(crate) noexec: bool,
+    pub(crate) nosuid: bool,
+    pub(crate) nodev: bool,
+    pub(crate) noatime: bool,
+    /// If None, propagation type is inherited from the source mount.
+    pub(crate) propagation: Option<MountPropagation>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub(crate) enum MountPropagation {
+    Private,
+    Slave,
 }
 
 const ID_CHARS: &[u8] = b"qwertyuiopasdfghjklzxcvbnm1234567890";
@@ -59,6 +71,8 @@ pub(crate) struct JobQuery {
     /// These are just "names" of fds, actual ownership
     /// is passed in subsequent query (right after stdio)
     pub(crate) extra_fds: Vec<i32>,
+    /// Overrides sandbox policy, if set
+    pub(in crate::linux) seccomp: Option<Seccomp>,
 }
 
 /// Asks zygote for exit code of **completed** task.
@@ -67,10 +81,32 @@ pub(crate) struct GetExitCodeQuery {
     pub(crate) pid: Pid,
 }
 
+/// Asks zygote to unmount previously exposed item.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct UnexposeQuery {
+    /// Path for child, relative to sandbox root
+    pub(crate) dest: PathBuf,
+}
+
+/// Asks zygote to open file inside sandbox and send its descriptor.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct OpenFileQuery {
+    /// Path for child, relative to sandbox root
+    pub(crate) path: PathBuf,
+    /// If set, file is created or truncated for writing and gets this mode.
+    /// Otherwise, file is opened for reading.
+    pub(crate) create_mode: Option<u32>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub(crate) struct ResourceUsageInformation {
     pub(crate) memory: u64,
-    pub(crate) cpu: u64,
+    pub(crate) user_time: u64,
+    pub(crate) system_time: u64,
+    pub(crate) major_page_faults: u64,
+    pub(crate) minor_page_faults: u64,
+    pub(crate) voluntary_context_switches: u64,
+    pub(crate) involuntary_context_switches: u64,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -89,6 +125,20 @@ pub(crate) enum Query {
     Spawn(JobQuery),
     GetExitCode(GetExitCodeQuery),
     GetResourceUsage,
+    /// Followed by detached mount tree of the item (see
+    /// `mount_api::detached_tree`).
+    /// Zygote replies with `Result<(), String>`
+    Expose(LinuxSharedItem),
+    /// Zygote replies with `Result<(), String>`
+    Unexpose(UnexposeQuery),
+    /// Zygote replies with `Result<(), String>`, followed by
+    /// file descriptor on success
+    OpenFile(OpenFileQuery),
+    /// Zygote replies with `Vec<SecurityViolation>` of the given task
+    /// (and its descendants), or of all tasks
+    GetSecurityViolations(Option<Pid>),
+    /// Zygote replies with `BTreeSet<i64>`
+    GetUsedSyscalls,
 }
 
 #[derive(Debug)]
@@ -1,21 +1,40 @@
 //! Implements resource limits
 mod cgroup_common;
+mod cgroup_mounts;
 mod cgroup_v1;
 mod cgroup_v2;
 mod prlimit;
 
 use self::{cgroup_common::CgroupEnter, prlimit::PrlimitEnter};
-use crate::linux::{jail_common::ZygoteInfo, Error, ResourceDriverKind, Settings};
+use crate::{
+    linux::{jail_common::ZygoteInfo, Error, MemoryRlimit, ResourceDriverKind, Settings},
+    CpuBandwidth, Cpuset, IoLimit, MemoryBreakdown, SwapPolicy,
+};
 use parking_lot::Mutex;
 use rand::Rng;
-use std::{collections::HashMap, error::Error as _, fmt, path::PathBuf, sync::Arc};
+use std::{
+    collections::HashMap, convert::TryInto, error::Error as _, fmt, path::PathBuf, sync::Arc,
+    time::Duration,
+};
 
 /// See ResourceUsageData for docs.
 #[derive(Debug, Copy, Clone, Default)]
 pub(in crate::linux) struct InternalResourceUsageData {
-    /// Non-optional (this is the only difference from ResourceUsageData)
+    /// Non-optional (unlike in ResourceUsageData)
     pub time: u64,
+    pub user_time: Option<u64>,
+    pub system_time: Option<u64>,
+    /// Peak memory usage
     pub memory: Option<u64>,
+    pub memory_current: Option<u64>,
+    pub memory_breakdown: Option<MemoryBreakdown>,
+    pub io_read: Option<u64>,
+    pub io_write: Option<u64>,
+    pub major_page_faults: Option<u64>,
+    pub minor_page_faults: Option<u64>,
+    pub voluntary_context_switches: Option<u64>,
+    pub involuntary_context_switches: Option<u64>,
+    pub pids_peak: Option<u64>,
 }
 
 /// Represents resource limits imposed on sandbox
@@ -23,7 +42,31 @@ pub(in crate::linux) struct InternalResourceUsageData {
 pub(in crate::linux) struct ResourceLimits {
     pub(in crate::linux) pids_max: u32,
     pub(in crate::linux) memory_max: u64,
+    pub(in crate::linux) memory_high: Option<u64>,
+    pub(in crate::linux) swap: SwapPolicy,
     pub(in crate::linux) cpu_usage: u64,
+    pub(in crate::linux) cpu_bandwidth: Option<CpuBandwidth>,
+    pub(in crate::linux) cpuset: Option<Cpuset>,
+    pub(in crate::linux) io_limits: Vec<IoLimit>,
+}
+
+impl ResourceLimits {
+    /// Returns CPU bandwidth quota and period in microseconds.
+    fn cpu_max(&self) -> Option<(u64, u64)> {
+        self.cpu_bandwidth.map(|bw| {
+            let micros = |d: Duration| d.as_micros().try_into().unwrap_or(u64::MAX);
+            (micros(bw.quota), micros(bw.period))
+        })
+    }
+}
+
+/// Formats list in the format used by `cpuset.cpus` and `cpuset.mems`.
+fn cpu_list(items: &[u32]) -> String {
+    items
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
 }
 
 trait ResourceLimitImpl {
@@ -44,6 +87,35 @@ trait ResourceLimitImpl {
     fn delete_group(&self, group_id: &str) -> Result<(), Self::Error>;
 
     fn resource_usage(&self, group_id: &str) -> Result<InternalResourceUsageData, Self::Error>;
+
+    /// Returns true if process creation failed because of `pids_max`.
+    fn pids_limit_hit(&self, _group_id: &str) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+
+    /// Returns true if OOM killer killed some process because of `memory_max`.
+    fn memory_limit_hit(&self, _group_id: &str) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+
+    /// Sends SIGKILL to all processes in the group. Does not wait until
+    /// they exit, see `wait_group_empty`.
+    fn kill_group(&self, _group_id: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Waits until all processes in the killed group exit, killing ones
+    /// which were created meanwhile. Returns false on timeout.
+    fn wait_group_empty(&self, _group_id: &str, _timeout: Duration) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    /// Kills processes and deletes groups, which are not used by any live
+    /// backend (including ones in other processes).
+    /// Returns ids of deleted groups.
+    fn cleanup_stale_groups(&self) -> Result<Vec<String>, Self::Error> {
+        Ok(Vec::new())
+    }
 }
 
 trait EnterHandle: Clone {
@@ -65,16 +137,39 @@ fn smoke_check<R: ResourceLimitImpl>(imp: &R) -> Result<(), Error> {
             &group_id,
             &ResourceLimits {
                 memory_max: 1 << 30,
+                memory_high: None,
+                swap: SwapPolicy::Deny,
                 pids_max: 1,
                 cpu_usage: 1_000_000_000,
+                cpu_bandwidth: None,
+                cpuset: None,
+                io_limits: Vec::new(),
             },
         )
         .map_err(Into::into)?;
 
     cgroup.check_access()?;
+    imp.delete_group(&group_id).map_err(Into::into)?;
     Ok(())
 }
 
+/// Returns controllers (and what they are used for) which can be used by
+/// the cgroup v1 driver, but are not mounted. Returns nothing if there are
+/// no cgroup v1 hierarchies at all, e.g. on systems with cgroup v2 only.
+pub(in crate::linux) fn missing_v1_controllers(
+    settings: &Settings,
+) -> Vec<(&'static str, &'static str)> {
+    let hierarchies = match cgroup_mounts::find_v1_hierarchies(&settings.cgroup.mount) {
+        Some(hierarchies) if !hierarchies.is_empty() => hierarchies,
+        _ => return Vec::new(),
+    };
+    cgroup_mounts::V1_CONTROLLERS
+        .iter()
+        .filter(|(name, _)| !hierarchies.contains_key(*name))
+        .copied()
+        .collect()
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum DriverError {
     #[error("cgroup manipulation failed")]
@@ -158,9 +253,13 @@ enum RawSettings {
         prefix: PathBuf,
         mount: PathBuf,
         version: CgroupVersion,
+        /// Prefix is relative to the delegated cgroup of the current process
+        delegated: bool,
     },
     Prlimit {
         allow_many_pids: bool,
+        memory_rlimit: MemoryRlimit,
+        rss_watchdog: bool,
     },
 }
 
@@ -183,12 +282,14 @@ fn process_driver_kind(settings: &Settings, out: &mut Vec<RawSettings>, kind: &R
                 prefix: settings.cgroup.name_prefix.clone(),
                 mount: settings.cgroup.mount.clone(),
                 version: CgroupVersion::V1,
+                delegated: false,
             });
         }
         ResourceDriverKind::CgroupV2 => out.push(RawSettings::Cgroup {
             prefix: settings.cgroup.name_prefix.clone(),
             mount: settings.cgroup.mount.clone(),
             version: CgroupVersion::V2,
+            delegated: settings.cgroup.delegated,
         }),
         ResourceDriverKind::CgroupAuto => {
             process_driver_kind(settings, out, &ResourceDriverKind::CgroupV1);
@@ -196,6 +297,8 @@ fn process_driver_kind(settings: &Settings, out: &mut Vec<RawSettings>, kind: &R
         }
         ResourceDriverKind::Prlimit => out.push(RawSettings::Prlimit {
             allow_many_pids: !settings.rootless,
+            memory_rlimit: settings.prlimit.memory_rlimit,
+            rss_watchdog: settings.prlimit.rss_watchdog,
         }),
         ResourceDriverKind::Auto { allow_dangerous } => {
             process_driver_kind(settings, out, &ResourceDriverKind::CgroupAuto);
@@ -207,37 +310,62 @@ fn process_driver_kind(settings: &Settings, out: &mut Vec<RawSettings>, kind: &R
 }
 
 impl Driver {
-    fn new_raw(raw_settings: &RawSettings) -> Self {
+    fn new_raw(raw_settings: &RawSettings) -> Result<Self, Error> {
         let inner = match raw_settings {
             RawSettings::Cgroup {
                 prefix,
                 mount,
                 version,
+                delegated,
             } => {
                 let mut cgroup_prefix = Vec::new();
-                for comp in prefix.components() {
+                // current process is only moved by `prepare`
+                let delegated_root = if *delegated {
+                    Some(cgroup_v2::find_delegated_root(mount).map_err(DriverError::from)?)
+                } else {
+                    None
+                };
+                let root_components = delegated_root.iter().flat_map(|root| root.components());
+                for comp in root_components.chain(prefix.components()) {
                     if let std::path::Component::Normal(n) = comp {
                         cgroup_prefix.push(n.to_os_string());
                     }
                 }
                 let cgroupfs_path = mount.clone();
                 match version {
-                    CgroupVersion::V1 => Inner::CgroupV1(cgroup_v1::CgroupV1 {
-                        cgroupfs_path,
-                        cgroup_prefix,
-                    }),
+                    CgroupVersion::V1 => Inner::CgroupV1(
+                        cgroup_v1::CgroupV1::new(cgroupfs_path, cgroup_prefix)
+                            .map_err(DriverError::from)?,
+                    ),
                     CgroupVersion::V2 => Inner::CgroupV2(cgroup_v2::CgroupV2 {
                         cgroupfs_path,
                         cgroup_prefix,
+                        locks: Mutex::new(HashMap::new()),
+                        delegated_root,
                     }),
                 }
             }
-            RawSettings::Prlimit { allow_many_pids } => Inner::Prlimit(prlimit::Prlimit {
+            RawSettings::Prlimit {
+                allow_many_pids,
+                memory_rlimit,
+                rss_watchdog,
+            } => Inner::Prlimit(prlimit::Prlimit {
                 groups: Mutex::new(HashMap::new()),
                 allow_multiple_processes: *allow_many_pids,
+                memory_rlimit: *memory_rlimit,
+                rss_watchdog: *rss_watchdog,
             }),
         };
-        Driver { inner }
+        Ok(Driver { inner })
+    }
+
+    /// Performs one-time setup of the selected driver.
+    fn prepare(&self) -> Result<(), Error> {
+        match &self.inner {
+            Inner::CgroupV2(inner) => inner.prepare().map_err(DriverError::from)?,
+            Inner::CgroupV1(_) | Inner::Prlimit(_) => {}
+        }
+        Ok(())
     }
 
     fn smoke_check(&self) -> Result<(), Error> {
@@ -258,9 +386,13 @@ impl Driver {
             attempts: Vec::new(),
         };
         for config in configs {
-            let driver = Self::new_raw(&config);
-            match driver.smoke_check() {
-                Ok(()) => {
+            let res = Self::new_raw(&config).and_then(|driver| {
+                driver.prepare()?;
+                driver.smoke_check()?;
+                Ok(driver)
+            });
+            match res {
+                Ok(driver) => {
                     tracing::debug!(settings=?config, "Found working configuration");
                     return Ok(driver);
                 }
@@ -295,6 +427,24 @@ impl Driver {
         Ok(res)
     }
 
+    pub fn pids_limit_hit(&self, group_id: &str) -> Result<bool, DriverError> {
+        let res = match &self.inner {
+            Inner::CgroupV1(inner) => inner.pids_limit_hit(group_id)?,
+            Inner::CgroupV2(inner) => inner.pids_limit_hit(group_id)?,
+            Inner::Prlimit(inner) => inner.pids_limit_hit(group_id)?,
+        };
+        Ok(res)
+    }
+
+    pub fn memory_limit_hit(&self, group_id: &str) -> Result<bool, DriverError> {
+        let res = match &self.inner {
+            Inner::CgroupV1(inner) => inner.memory_limit_hit(group_id)?,
+            Inner::CgroupV2(inner) => inner.memory_limit_hit(group_id)?,
+            Inner::Prlimit(inner) => inner.memory_limit_hit(group_id)?,
+        };
+        Ok(res)
+    }
+
     pub fn delete_group(&self, group_id: &str) -> Result<(), DriverError> {
         match &self.inner {
             Inner::CgroupV1(inner) => inner.delete_group(group_id)?,
@@ -304,6 +454,33 @@ impl Driver {
         Ok(())
     }
 
+    pub fn kill_group(&self, group_id: &str) -> Result<(), DriverError> {
+        match &self.inner {
+            Inner::CgroupV1(inner) => inner.kill_group(group_id)?,
+            Inner::CgroupV2(inner) => inner.kill_group(group_id)?,
+            Inner::Prlimit(inner) => inner.kill_group(group_id)?,
+        };
+        Ok(())
+    }
+
+    pub fn wait_group_empty(&self, group_id: &str, timeout: Duration) -> Result<bool, DriverError> {
+        let res = match &self.inner {
+            Inner::CgroupV1(inner) => inner.wait_group_empty(group_id, timeout)?,
+            Inner::CgroupV2(inner) => inner.wait_group_empty(group_id, timeout)?,
+            Inner::Prlimit(inner) => inner.wait_group_empty(group_id, timeout)?,
+        };
+        Ok(res)
+    }
+
+    pub fn cleanup_stale_groups(&self) -> Result<Vec<String>, DriverError> {
+        let res = match &self.inner {
+            Inner::CgroupV1(inner) => inner.cleanup_stale_groups()?,
+            Inner::CgroupV2(inner) => inner.cleanup_stale_groups()?,
+            Inner::Prlimit(inner) => inner.cleanup_stale_groups()?,
+        };
+        Ok(res)
+    }
+
     pub fn register_group_details(&self, group_id: &str, zygote: Arc<Mutex<Option<ZygoteInfo>>>) {
         match &self.inner {
             Inner::CgroupV1(inner) => inner.register_group_details(group_id, zygote),
@@ -312,6 +489,26 @@ impl Driver {
         }
     }
 
+    /// Returns true if `limits` can only be enforced
+    /// when creation of new processes is denied.
+    pub fn needs_single_process(&self, limits: &ResourceLimits) -> bool {
+        match &self.inner {
+            Inner::CgroupV1(_) => false,
+            Inner::CgroupV2(_) => false,
+            Inner::Prlimit(inner) => inner.needs_single_process(limits),
+        }
+    }
+
+    /// Returns true if memory limit must be enforced by the watchdog,
+    /// using `InternalResourceUsageData::memory_current`.
+    pub fn needs_memory_watchdog(&self) -> bool {
+        match &self.inner {
+            Inner::CgroupV1(_) => false,
+            Inner::CgroupV2(_) => false,
+            Inner::Prlimit(inner) => inner.rss_watchdog,
+        }
+    }
+
     pub fn get_watchdog(&self) -> bool {
         match &self.inner {
             Inner::CgroupV1(_) => true,
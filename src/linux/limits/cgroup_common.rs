@@ -1,5 +1,21 @@
 use crate::linux::{limits::EnterHandle, Error};
-use std::{os::unix::prelude::RawFd, path::PathBuf};
+use nix::{
+    fcntl::FlockArg,
+    poll::{PollFd, PollFlags},
+};
+use std::{
+    fs::File,
+    os::unix::{
+        fs::MetadataExt,
+        prelude::{AsRawFd, RawFd},
+    },
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// How long `cleanup_stale_groups` waits for processes of a stale group
+/// to exit.
+pub(super) const KILL_TIMEOUT: Duration = Duration::from_secs(1);
 
 /// Information, sufficient for joining a cgroup.
 #[derive(Clone)]
@@ -83,6 +99,179 @@ impl Drop for CgroupEnter {
     }
 }
 
+/// Returns `major:minor` of the block device, as used by I/O controllers.
+pub(super) fn device_number(device: &Path) -> Result<String, CgroupError> {
+    let metadata = std::fs::metadata(device).map_err(|cause| CgroupError::Device {
+        path: device.to_path_buf(),
+        cause,
+    })?;
+    let rdev = metadata.rdev();
+    Ok(format!(
+        "{}:{}",
+        nix::sys::stat::major(rdev),
+        nix::sys::stat::minor(rdev)
+    ))
+}
+
+/// Returns value of `key` in flat keyed files like `memory.stat`,
+/// which have lines in form `<key> <value>`.
+pub(super) fn flat_keyed_value(data: &str, key: &str) -> Option<u64> {
+    data.lines().find_map(|line| {
+        let mut parts = line.split_ascii_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some(k), Some(value)) if k == key => value.parse().ok(),
+            _ => None,
+        }
+    })
+}
+
+/// Reads optional single-value file, e.g. `pids.peak` which is only
+/// available since Linux 6.1.
+pub(super) fn read_optional_value(path: &Path) -> Option<u64> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Sums `key` column in files like `blkio.throttle.io_service_bytes`,
+/// which have lines in form `<device> <key> <value>`.
+pub(super) fn sum_v1_io_stat(data: &str, key: &str) -> u64 {
+    data.lines()
+        .filter_map(|line| {
+            let mut parts = line.split_ascii_whitespace();
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(_), Some(k), Some(value)) if k == key => value.parse::<u64>().ok(),
+                _ => None,
+            }
+        })
+        .sum()
+}
+
+/// Sums `key` fields in `io.stat`, which has lines in form
+/// `<device> <key>=<value> <key>=<value>...`.
+pub(super) fn sum_v2_io_stat(data: &str, key: &str) -> u64 {
+    data.lines()
+        .flat_map(|line| line.split_ascii_whitespace().skip(1))
+        .filter_map(|field| {
+            let mut kv = field.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(k), Some(value)) if k == key => value.parse::<u64>().ok(),
+                _ => None,
+            }
+        })
+        .sum()
+}
+
+/// Opens directory and locks it with `flock`. Such locks belong to the open
+/// file, so they are released when the owner process dies (or closes the
+/// returned file), and not when some other descriptor of the directory
+/// is closed. Returns `None` if `arg` is non-blocking and the directory
+/// is already locked.
+pub(super) fn lock_dir(path: &Path, arg: FlockArg) -> Result<Option<File>, CgroupError> {
+    let dir = File::open(path).map_err(|cause| CgroupError::OpenFile {
+        path: path.to_path_buf(),
+        cause,
+    })?;
+    match nix::fcntl::flock(dir.as_raw_fd(), arg) {
+        Ok(()) => Ok(Some(dir)),
+        Err(nix::Error::Sys(nix::errno::Errno::EAGAIN)) => Ok(None),
+        Err(cause) => Err(CgroupError::Lock {
+            path: path.to_path_buf(),
+            cause,
+        }),
+    }
+}
+
+/// Returns true if sandbox cgroup is locked by a live backend.
+pub(super) fn is_locked(path: &Path) -> Result<bool, CgroupError> {
+    match lock_dir(path, FlockArg::LockExclusiveNonblock) {
+        Ok(lock) => Ok(lock.is_none()),
+        Err(err) if err.is_not_found() => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Returns ids of sandbox cgroups in `prefix_path`.
+pub(super) fn list_groups(prefix_path: &Path) -> Result<Vec<String>, CgroupError> {
+    let entries = match std::fs::read_dir(prefix_path) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(cause) => {
+            return Err(CgroupError::Read {
+                path: prefix_path.to_path_buf(),
+                cause,
+            })
+        }
+    };
+    let mut groups = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|cause| CgroupError::Read {
+            path: prefix_path.to_path_buf(),
+            cause,
+        })?;
+        if let Some(name) = entry.file_name().to_str() {
+            if let Some(group_id) = name.strip_prefix("sandbox.") {
+                groups.push(group_id.to_string());
+            }
+        }
+    }
+    Ok(groups)
+}
+
+/// Sends SIGKILL to all processes listed in `procs_path` (`tasks` or
+/// `cgroup.procs`). Returns false if there are no processes.
+pub(super) fn signal_all(procs_path: &Path) -> Result<bool, CgroupError> {
+    let data = match std::fs::read_to_string(procs_path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(cause) => {
+            return Err(CgroupError::Read {
+                path: procs_path.to_path_buf(),
+                cause,
+            })
+        }
+    };
+    let pids = data
+        .lines()
+        .filter_map(|line| line.trim().parse().ok())
+        .collect::<Vec<i32>>();
+    for &pid in &pids {
+        nix::sys::signal::kill(
+            nix::unistd::Pid::from_raw(pid),
+            nix::sys::signal::Signal::SIGKILL,
+        )
+        .ok();
+    }
+    Ok(!pids.is_empty())
+}
+
+/// Waits until `fd` becomes readable or `deadline` passes.
+/// Returns false on timeout.
+pub(super) fn wait_readable(fd: RawFd, deadline: Instant) -> Result<bool, CgroupError> {
+    let mut poll_fds = [PollFd::new(fd, PollFlags::POLLIN)];
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+        match nix::poll::poll(&mut poll_fds, timeout_ms) {
+            Ok(0) => return Ok(false),
+            Ok(_) => return Ok(true),
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            Err(cause) => return Err(CgroupError::Wait { cause }),
+        }
+    }
+}
+
+impl CgroupError {
+    /// Returns true if cgroup file does not exist, e.g. because
+    /// kernel was built without corresponding option.
+    pub(super) fn is_not_found(&self) -> bool {
+        match self {
+            CgroupError::Write { cause, .. }
+            | CgroupError::Read { cause, .. }
+            | CgroupError::OpenFile { cause, .. } => cause.kind() == std::io::ErrorKind::NotFound,
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum CgroupError {
     #[error("failed to write data to {path}")]
@@ -109,6 +298,12 @@ pub enum CgroupError {
         #[source]
         cause: std::io::Error,
     },
+    #[error("failed to get device number of {path}")]
+    Device {
+        path: PathBuf,
+        #[source]
+        cause: std::io::Error,
+    },
     #[error("failed to duplicate handle")]
     DuplicateFd {
         #[source]
@@ -119,6 +314,23 @@ pub enum CgroupError {
         #[source]
         cause: nix::Error,
     },
+    #[error("failed to lock {path}")]
+    Lock {
+        path: PathBuf,
+        #[source]
+        cause: nix::Error,
+    },
+    #[error("processes in {path} survived SIGKILL")]
+    Kill { path: PathBuf },
+    #[error("failed to wait for cgroup events")]
+    Wait {
+        #[source]
+        cause: nix::Error,
+    },
+    #[error("cgroup v1 controller {name} is not mounted")]
+    MissingController { name: String },
+    #[error("cgroup v2 of the current process not found in /proc/self/cgroup")]
+    UnknownCurrentCgroup,
     /// This error can only happen during initialization
     /// as forking is part of smoke-tests and config detection.
     #[error("failed to fork")]
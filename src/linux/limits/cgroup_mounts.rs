@@ -0,0 +1,169 @@
+//! Discovers where cgroup v1 controllers are mounted
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// Controllers which are used by the v1 driver, and what they are used for
+pub(super) const V1_CONTROLLERS: &[(&str, &str)] = &[
+    ("pids", "process count limit"),
+    ("memory", "memory limit"),
+    ("cpuacct", "CPU time accounting"),
+    ("cpu", "CPU bandwidth limit"),
+    ("cpuset", "CPU pinning"),
+    ("blkio", "I/O limits and counters"),
+    ("freezer", "killing all processes at once"),
+];
+
+/// Mountinfo escapes space, tab, newline and backslash as octal sequences
+fn unescape(field: &str) -> String {
+    let mut out = Vec::with_capacity(field.len());
+    let bytes = field.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 4 <= bytes.len() {
+            let code = std::str::from_utf8(&bytes[i + 1..i + 4])
+                .ok()
+                .and_then(|code| u8::from_str_radix(code, 8).ok());
+            if let Some(ch) = code {
+                out.push(ch);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Returns mount point and controllers of each v1 hierarchy in
+/// `/proc/self/mountinfo` (see proc(5) for the format).
+fn parse_mountinfo(data: &str) -> Vec<(PathBuf, Vec<String>)> {
+    let mut hierarchies = Vec::new();
+    for line in data.lines() {
+        let mut parts = line.splitn(2, " - ");
+        let (mount_fields, fs_fields) = match (parts.next(), parts.next()) {
+            (Some(m), Some(f)) => (m, f),
+            _ => continue,
+        };
+        let mount_point = match mount_fields.split(' ').nth(4) {
+            Some(p) => unescape(p),
+            None => continue,
+        };
+        let mut fs_fields = fs_fields.split(' ');
+        if fs_fields.next() != Some("cgroup") {
+            continue;
+        }
+        let super_options = match fs_fields.nth(1) {
+            Some(o) => o,
+            None => continue,
+        };
+        let controllers = super_options
+            .split(',')
+            .filter(|opt| V1_CONTROLLERS.iter().any(|(name, _)| name == opt))
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        if !controllers.is_empty() {
+            hierarchies.push((PathBuf::from(mount_point), controllers));
+        }
+    }
+    hierarchies
+}
+
+/// Returns names of controllers which are enabled in `/proc/cgroups`
+fn parse_proc_cgroups(data: &str) -> Vec<String> {
+    data.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields = line.split_ascii_whitespace().collect::<Vec<_>>();
+            match fields.as_slice() {
+                [name, _hierarchy, _num_cgroups, "1"] => Some(name.to_string()),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Returns mount point for each v1 controller. If a controller is mounted
+/// several times, mount point inside `preferred_root` is used.
+/// Controllers which are co-mounted (e.g. `cpu,cpuacct`) share the
+/// mount point. Returns `None` if procfs is not available.
+pub(super) fn find_v1_hierarchies(preferred_root: &Path) -> Option<HashMap<String, PathBuf>> {
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").ok()?;
+    // if /proc/cgroups can not be read, mounted controllers are assumed
+    // to be enabled
+    let enabled = std::fs::read_to_string("/proc/cgroups")
+        .ok()
+        .map(|data| parse_proc_cgroups(&data));
+    let mut hierarchies: HashMap<String, PathBuf> = HashMap::new();
+    for (mount_point, controllers) in parse_mountinfo(&mountinfo) {
+        for controller in controllers {
+            if let Some(enabled) = &enabled {
+                if !enabled.contains(&controller) {
+                    continue;
+                }
+            }
+            let preferred = mount_point.starts_with(preferred_root);
+            match hierarchies.get(&controller) {
+                Some(prev) if !preferred || prev.starts_with(preferred_root) => {}
+                _ => {
+                    hierarchies.insert(controller, mount_point.clone());
+                }
+            }
+        }
+    }
+    Some(hierarchies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("/sys/fs/cgroup"), "/sys/fs/cgroup");
+        assert_eq!(unescape("/mnt/my\\040dir\\011x"), "/mnt/my dir\tx");
+        assert_eq!(unescape("a\\134b"), "a\\b");
+        // not an octal escape
+        assert_eq!(unescape("a\\900b"), "a\\900b");
+        assert_eq!(unescape("a\\777b"), "a\\777b");
+        assert_eq!(unescape("trailing\\04"), "trailing\\04");
+    }
+
+    #[test]
+    fn test_parse_mountinfo() {
+        let data = "\
+22 1 0:20 / /sys rw,nosuid - sysfs sysfs rw
+25 22 0:23 / /sys/fs/cgroup ro,nosuid - tmpfs tmpfs ro,mode=755
+26 25 0:24 / /sys/fs/cgroup/unified rw,nosuid shared:5 - cgroup2 cgroup2 rw
+30 25 0:28 / /sys/fs/cgroup/cpu,cpuacct rw,nosuid shared:11 - cgroup cgroup rw,cpu,cpuacct
+31 25 0:29 / /sys/fs/cgroup/systemd rw,nosuid shared:12 - cgroup cgroup rw,xattr,name=systemd
+32 25 0:30 / /mnt/my\\040cgroups rw - cgroup cgroup rw,pids
+";
+        assert_eq!(
+            parse_mountinfo(data),
+            vec![
+                (
+                    PathBuf::from("/sys/fs/cgroup/cpu,cpuacct"),
+                    vec!["cpu".to_string(), "cpuacct".to_string()]
+                ),
+                (PathBuf::from("/mnt/my cgroups"), vec!["pids".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_proc_cgroups() {
+        let data = "\
+#subsys_name\thierarchy\tnum_cgroups\tenabled
+cpuset\t2\t1\t1
+memory\t0\t105\t0
+pids\t5\t110\t1
+";
+        assert_eq!(
+            parse_proc_cgroups(data),
+            vec!["cpuset".to_string(), "pids".to_string()]
+        );
+    }
+}
@@ -1,29 +1,135 @@
 //! Implements Cgroup Driver for V1 cgroups
 use crate::linux::limits::{
-    cgroup_common::{CgroupEnter, CgroupError},
-    InternalResourceUsageData, ResourceLimitImpl, ResourceLimits,
+    cgroup_common::{self, CgroupEnter, CgroupError},
+    cgroup_mounts::{self, V1_CONTROLLERS},
+    cpu_list, InternalResourceUsageData, ResourceLimitImpl, ResourceLimits, SwapPolicy,
 };
-use std::{ffi::OsString, os::unix::io::IntoRawFd, path::PathBuf};
+use crate::MemoryBreakdown;
+use nix::fcntl::FlockArg;
+use parking_lot::Mutex;
+use std::{
+    collections::{BTreeSet, HashMap},
+    ffi::OsString,
+    fs::File,
+    os::unix::io::IntoRawFd,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+/// How often `wait_group_empty` re-reads task lists
+const EMPTY_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Controllers without which the driver does not work. Others are
+/// only required if corresponding options are used.
+const REQUIRED_CONTROLLERS: &[&str] = &["pids", "memory", "cpuacct"];
 
 #[derive(Debug)]
 pub(super) struct CgroupV1 {
-    pub(super) cgroupfs_path: PathBuf,
-    pub(super) cgroup_prefix: Vec<OsString>,
+    cgroupfs_path: PathBuf,
+    cgroup_prefix: Vec<OsString>,
+    /// Mount point of each available controller
+    hierarchies: HashMap<String, PathBuf>,
+    /// Locked `pids` directories of groups created by this instance.
+    /// See `cgroup_common::lock_dir`.
+    locks: Mutex<HashMap<String, File>>,
 }
 
 impl CgroupV1 {
+    pub(super) fn new(
+        cgroupfs_path: PathBuf,
+        cgroup_prefix: Vec<OsString>,
+    ) -> Result<Self, CgroupError> {
+        // without procfs, assume that each controller is mounted
+        // separately in `cgroupfs_path`
+        let hierarchies = cgroup_mounts::find_v1_hierarchies(&cgroupfs_path).unwrap_or_else(|| {
+            V1_CONTROLLERS
+                .iter()
+                .map(|(name, _)| (name.to_string(), cgroupfs_path.join(name)))
+                .collect()
+        });
+        for name in REQUIRED_CONTROLLERS {
+            if !hierarchies.contains_key(*name) {
+                return Err(CgroupError::MissingController {
+                    name: name.to_string(),
+                });
+            }
+        }
+        Ok(CgroupV1 {
+            cgroupfs_path,
+            cgroup_prefix,
+            hierarchies,
+            locks: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn has_controller(&self, subsys_name: &str) -> bool {
+        self.hierarchies.contains_key(subsys_name)
+    }
+
+    fn require_controller(&self, subsys_name: &str) -> Result<(), CgroupError> {
+        if self.has_controller(subsys_name) {
+            Ok(())
+        } else {
+            Err(CgroupError::MissingController {
+                name: subsys_name.to_string(),
+            })
+        }
+    }
+
+    /// Returns all available controllers which can be used by a sandbox
+    fn controllers(&self) -> impl Iterator<Item = &'static str> + '_ {
+        V1_CONTROLLERS
+            .iter()
+            .map(|(name, _)| *name)
+            .filter(move |name| self.has_controller(name))
+    }
+
+    /// Returns one available controller per hierarchy, so that
+    /// co-mounted controllers (e.g. `cpu,cpuacct`), which share
+    /// directories, are only visited once.
+    fn distinct_controllers(&self) -> Vec<&'static str> {
+        let mut roots = Vec::new();
+        let mut controllers = Vec::new();
+        for subsys in self.controllers() {
+            let root = &self.hierarchies[subsys];
+            if !roots.contains(&root) {
+                roots.push(root);
+                controllers.push(subsys);
+            }
+        }
+        controllers
+    }
+
+    fn get_hierarchy_root(&self, subsys_name: &str) -> PathBuf {
+        // missing controllers are checked before use, this path is only
+        // used in error messages
+        self.hierarchies
+            .get(subsys_name)
+            .cloned()
+            .unwrap_or_else(|| self.cgroupfs_path.join(subsys_name))
+    }
+
     fn write_file(
         &self,
         cgroup_id: &str,
         subsys_name: &str,
         file_name: &str,
         num: u64,
+    ) -> Result<(), CgroupError> {
+        let mut buf = itoa::Buffer::new();
+        self.write_str(cgroup_id, subsys_name, file_name, buf.format(num))
+    }
+
+    fn write_str(
+        &self,
+        cgroup_id: &str,
+        subsys_name: &str,
+        file_name: &str,
+        data: &str,
     ) -> Result<(), CgroupError> {
         let path = self
             .get_path_for_cgroup_legacy_subsystem(subsys_name, cgroup_id)
             .join(file_name);
-        let mut buf = itoa::Buffer::new();
-        let data = buf.format(num);
 
         std::fs::write(&path, data).map_err(|cause| CgroupError::Write { path, cause })
     }
@@ -47,6 +153,30 @@ impl CgroupV1 {
         std::fs::create_dir_all(&path).map_err(|cause| CgroupError::CreateCgroupDir { path, cause })
     }
 
+    /// Returns user and system CPU time in nanoseconds.
+    fn get_cpu_stat(&self, cgroup_id: &str) -> Result<(Option<u64>, Option<u64>), CgroupError> {
+        let stat_data = self.read_file(cgroup_id, "cpuacct", "cpuacct.stat")?;
+        // values are in USER_HZ
+        let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as u64;
+        let nanos = |key| {
+            cgroup_common::flat_keyed_value(&stat_data, key)
+                .map(|ticks| ticks * 1_000_000_000 / ticks_per_second)
+        };
+        Ok((nanos("user"), nanos("system")))
+    }
+
+    /// Returns major and minor page faults
+    fn get_page_faults(&self, cgroup_id: &str) -> Result<(Option<u64>, Option<u64>), CgroupError> {
+        let stat_data = self.read_file(cgroup_id, "memory", "memory.stat")?;
+        let faults = cgroup_common::flat_keyed_value(&stat_data, "total_pgfault");
+        let major = cgroup_common::flat_keyed_value(&stat_data, "total_pgmajfault");
+        // pgfault includes major faults
+        let minor = faults
+            .zip(major)
+            .map(|(faults, major)| faults.saturating_sub(major));
+        Ok((major, minor))
+    }
+
     fn get_memory_usage(&self, cgroup_id: &str) -> Result<u64, CgroupError> {
         let usage = self
             .read_file(cgroup_id, "memory", "memory.max_usage_in_bytes")?
@@ -56,6 +186,24 @@ impl CgroupV1 {
         Ok(usage)
     }
 
+    fn get_current_memory_usage(&self, cgroup_id: &str) -> Result<u64, CgroupError> {
+        let usage = self
+            .read_file(cgroup_id, "memory", "memory.usage_in_bytes")?
+            .trim()
+            .parse()
+            .unwrap();
+        Ok(usage)
+    }
+
+    fn get_memory_breakdown(&self, cgroup_id: &str) -> Result<MemoryBreakdown, CgroupError> {
+        let stat_data = self.read_file(cgroup_id, "memory", "memory.stat")?;
+        // `total_` values include descendant cgroups
+        Ok(MemoryBreakdown {
+            anon: cgroup_common::flat_keyed_value(&stat_data, "total_rss").unwrap_or(0),
+            file: cgroup_common::flat_keyed_value(&stat_data, "total_cache").unwrap_or(0),
+        })
+    }
+
     fn get_cpu_usage(&self, cgroup_id: &str) -> Result<u64, CgroupError> {
         let usage = self
             .read_file(cgroup_id, "cpuacct", "cpuacct.usage")?
@@ -65,21 +213,70 @@ impl CgroupV1 {
         Ok(usage)
     }
 
-    fn drop_cgroup(&self, cgroup_id: &str, subsystems: &[&str]) {
-        for subsys in subsystems {
+    /// New cpuset cgroups have no CPUs and memory nodes, so no task can
+    /// join them. Fills them from the parent cgroup, starting from the
+    /// top of the hierarchy.
+    fn populate_cpuset(&self, cgroup_id: &str) -> Result<(), CgroupError> {
+        let root = self.get_hierarchy_root("cpuset");
+        let path = self.get_path_for_cgroup_legacy_subsystem("cpuset", cgroup_id);
+        let mut levels = path
+            .ancestors()
+            .take_while(|dir| *dir != root)
+            .collect::<Vec<_>>();
+        levels.reverse();
+        for dir in levels {
+            for file_name in &["cpuset.cpus", "cpuset.mems"] {
+                let path = dir.join(file_name);
+                let data = std::fs::read_to_string(&path).map_err(|cause| CgroupError::Read {
+                    path: path.clone(),
+                    cause,
+                })?;
+                if !data.trim().is_empty() {
+                    continue;
+                }
+                let parent_path = dir.parent().unwrap().join(file_name);
+                let data =
+                    std::fs::read_to_string(&parent_path).map_err(|cause| CgroupError::Read {
+                        path: parent_path,
+                        cause,
+                    })?;
+                std::fs::write(&path, data.trim())
+                    .map_err(|cause| CgroupError::Write { path, cause })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns total bytes read and written, if blkio controller is available.
+    fn get_io_usage(&self, cgroup_id: &str) -> Result<Option<(u64, u64)>, CgroupError> {
+        if !self.has_controller("blkio") {
+            return Ok(None);
+        }
+        let stat_data = self.read_file(cgroup_id, "blkio", "blkio.throttle.io_service_bytes")?;
+        Ok(Some((
+            cgroup_common::sum_v1_io_stat(&stat_data, "Read"),
+            cgroup_common::sum_v1_io_stat(&stat_data, "Write"),
+        )))
+    }
+
+    fn drop_cgroup(&self, cgroup_id: &str) {
+        for subsys in self.distinct_controllers() {
             std::fs::remove_dir(self.get_path_for_cgroup_legacy_subsystem(subsys, cgroup_id)).ok();
         }
     }
 
-    fn get_path_for_cgroup_legacy_subsystem(&self, subsys_name: &str, cgroup_id: &str) -> PathBuf {
-        let mut p = self.cgroupfs_path.clone();
-        p.push(subsys_name);
+    fn get_prefix_for_subsystem(&self, subsys_name: &str) -> PathBuf {
+        let mut p = self.get_hierarchy_root(subsys_name);
         for comp in &self.cgroup_prefix {
             p.push(comp);
         }
-        p.push(format!("sandbox.{}", cgroup_id));
         p
     }
+
+    fn get_path_for_cgroup_legacy_subsystem(&self, subsys_name: &str, cgroup_id: &str) -> PathBuf {
+        self.get_prefix_for_subsystem(subsys_name)
+            .join(format!("sandbox.{}", cgroup_id))
+    }
 }
 
 impl ResourceLimitImpl for CgroupV1 {
@@ -92,30 +289,155 @@ impl ResourceLimitImpl for CgroupV1 {
         group_id: &str,
         limits: &ResourceLimits,
     ) -> Result<Self::Enter, Self::Error> {
+        // optional controllers are checked before anything is created
+        if limits.cpu_bandwidth.is_some() {
+            self.require_controller("cpu")?;
+        }
+        if limits.cpuset.is_some() {
+            self.require_controller("cpuset")?;
+        }
+        if !limits.io_limits.is_empty() {
+            self.require_controller("blkio")?;
+        }
+
+        // pids cgroup is created first and locked, so that it is not
+        // considered stale by `cleanup_stale_groups`.
+        let prefix_path = self.get_prefix_for_subsystem("pids");
+        std::fs::create_dir_all(&prefix_path).map_err(|cause| CgroupError::CreateCgroupDir {
+            path: prefix_path.clone(),
+            cause,
+        })?;
+        let _prefix_lock = cgroup_common::lock_dir(&prefix_path, FlockArg::LockShared)?;
+        self.create_cgroup(group_id, "pids")?;
+        let pids_path = self.get_path_for_cgroup_legacy_subsystem("pids", group_id);
+        if let Some(lock) = cgroup_common::lock_dir(&pids_path, FlockArg::LockExclusive)? {
+            self.locks.lock().insert(group_id.to_string(), lock);
+        }
+
         // configure cpuacct subsystem
         self.create_cgroup(group_id, "cpuacct")?;
 
+        // freezer subsystem is used for killing processes
+        if self.has_controller("freezer") {
+            self.create_cgroup(group_id, "freezer")?;
+        }
+
+        // configure cpu subsystem
+        if let Some((quota, period)) = limits.cpu_max() {
+            self.create_cgroup(group_id, "cpu")?;
+            // period must be set first, because quota is validated against it
+            self.write_file(group_id, "cpu", "cpu.cfs_period_us", period)?;
+            self.write_file(group_id, "cpu", "cpu.cfs_quota_us", quota)?;
+        }
+
+        // configure cpuset subsystem
+        if let Some(cpuset) = &limits.cpuset {
+            self.create_cgroup(group_id, "cpuset")?;
+            self.populate_cpuset(group_id)?;
+            // empty list means that parent's list is used
+            if !cpuset.cpus.is_empty() {
+                self.write_str(group_id, "cpuset", "cpuset.cpus", &cpu_list(&cpuset.cpus))?;
+            }
+            if !cpuset.mems.is_empty() {
+                self.write_str(group_id, "cpuset", "cpuset.mems", &cpu_list(&cpuset.mems))?;
+            }
+        }
+
+        // configure blkio subsystem
+        // without it, I/O counters are not reported
+        if self.has_controller("blkio") {
+            self.create_cgroup(group_id, "blkio")?;
+        }
+        for limit in &limits.io_limits {
+            let device = cgroup_common::device_number(&limit.device)?;
+            let files = [
+                ("blkio.throttle.read_bps_device", limit.read_bps),
+                ("blkio.throttle.write_bps_device", limit.write_bps),
+                ("blkio.throttle.read_iops_device", limit.read_iops),
+                ("blkio.throttle.write_iops_device", limit.write_iops),
+            ];
+            for (file_name, value) in &files {
+                if let Some(value) = value {
+                    let data = format!("{} {}", device, value);
+                    self.write_str(group_id, "blkio", file_name, &data)?;
+                }
+            }
+        }
+
         // configure pids subsystem
-        self.create_cgroup(group_id, "pids")?;
         self.write_file(group_id, "pids", "pids.max", limits.pids_max.into())?;
 
         // configure memory subsystem
         self.create_cgroup(group_id, "memory")?;
-        self.write_file(group_id, "memory", "memory.swappiness", 0)?;
         self.write_file(
             group_id,
             "memory",
             "memory.limit_in_bytes",
             limits.memory_max,
         )?;
+        // memsw limits memory and swap together, it is only available
+        // if swap accounting is enabled.
+        match limits.swap {
+            SwapPolicy::Deny => {
+                self.write_file(group_id, "memory", "memory.swappiness", 0)?;
+                let res = self.write_file(
+                    group_id,
+                    "memory",
+                    "memory.memsw.limit_in_bytes",
+                    limits.memory_max,
+                );
+                match res {
+                    Err(err) if err.is_not_found() => {
+                        tracing::warn!("swap accounting is disabled, swap usage is not restricted")
+                    }
+                    res => res?,
+                }
+            }
+            SwapPolicy::Limit(swap_max) => self.write_file(
+                group_id,
+                "memory",
+                "memory.memsw.limit_in_bytes",
+                limits.memory_max.saturating_add(swap_max),
+            )?,
+            SwapPolicy::Unrestricted => {}
+        }
+        // v1 has no direct equivalent of memory.high, soft limit is only
+        // enforced when system is low on memory.
+        if let Some(memory_high) = limits.memory_high {
+            self.write_file(
+                group_id,
+                "memory",
+                "memory.soft_limit_in_bytes",
+                memory_high,
+            )?;
+        }
 
         // we return handles to tasksfiles for main cgroups
         // so, though zygote itself and children are in chroot, and cannot access cgroupfs, they will be able to add themselves to cgroups
-        let handles = ["cpuacct", "memory", "pids"]
-            .iter()
-            .map(|subsys_name| {
-                let p = self.get_path_for_cgroup_legacy_subsystem(subsys_name, group_id);
-                let p = p.join("tasks");
+        let mut subsystems = vec!["cpuacct", "memory", "pids"];
+        for subsys_name in &["blkio", "freezer"] {
+            if self.has_controller(subsys_name) {
+                subsystems.push(subsys_name);
+            }
+        }
+        if limits.cpu_bandwidth.is_some() {
+            subsystems.push("cpu");
+        }
+        if limits.cpuset.is_some() {
+            subsystems.push("cpuset");
+        }
+        // co-mounted controllers share `tasks` file
+        let mut tasks_paths = Vec::new();
+        for subsys_name in &subsystems {
+            let p = self.get_path_for_cgroup_legacy_subsystem(subsys_name, group_id);
+            let p = p.join("tasks");
+            if !tasks_paths.contains(&p) {
+                tasks_paths.push(p);
+            }
+        }
+        let handles = tasks_paths
+            .into_iter()
+            .map(|p| {
                 let h = std::fs::OpenOptions::new()
                     .write(true)
                     .open(&p)
@@ -128,14 +450,127 @@ impl ResourceLimitImpl for CgroupV1 {
     }
 
     fn resource_usage(&self, group_id: &str) -> Result<InternalResourceUsageData, Self::Error> {
+        let io = self.get_io_usage(group_id)?;
+        let (user_time, system_time) = self.get_cpu_stat(group_id)?;
+        let (major_page_faults, minor_page_faults) = self.get_page_faults(group_id)?;
         Ok(InternalResourceUsageData {
             time: (self.get_cpu_usage(group_id)?),
+            user_time,
+            system_time,
             memory: Some(self.get_memory_usage(group_id)?),
+            memory_current: Some(self.get_current_memory_usage(group_id)?),
+            memory_breakdown: Some(self.get_memory_breakdown(group_id)?),
+            io_read: io.map(|(read, _)| read),
+            io_write: io.map(|(_, write)| write),
+            major_page_faults,
+            minor_page_faults,
+            voluntary_context_switches: None,
+            involuntary_context_switches: None,
+            pids_peak: cgroup_common::read_optional_value(
+                &self
+                    .get_path_for_cgroup_legacy_subsystem("pids", group_id)
+                    .join("pids.peak"),
+            ),
         })
     }
 
+    fn pids_limit_hit(&self, group_id: &str) -> Result<bool, Self::Error> {
+        let events = self.read_file(group_id, "pids", "pids.events")?;
+        Ok(cgroup_common::flat_keyed_value(&events, "max").unwrap_or(0) > 0)
+    }
+
+    fn memory_limit_hit(&self, group_id: &str) -> Result<bool, Self::Error> {
+        // oom_kill is only reported since Linux 4.13
+        let oom_control = self.read_file(group_id, "memory", "memory.oom_control")?;
+        Ok(cgroup_common::flat_keyed_value(&oom_control, "oom_kill").unwrap_or(0) > 0)
+    }
+
     fn delete_group(&self, group_id: &str) -> Result<(), Self::Error> {
-        self.drop_cgroup(group_id, &["pids", "memory", "cpuacct"]);
+        self.drop_cgroup(group_id);
+        self.locks.lock().remove(group_id);
         Ok(())
     }
+
+    fn kill_group(&self, group_id: &str) -> Result<(), Self::Error> {
+        // Frozen processes can not create new ones, so all of them are
+        // killed at once. They only die after the cgroup is thawed.
+        // Freezing is not waited for, processes forked by not yet frozen
+        // ones are killed by `wait_group_empty`.
+        // Freezer cgroup can be missing in stale groups.
+        if self.has_controller("freezer") {
+            match self.write_str(group_id, "freezer", "freezer.state", "FROZEN") {
+                Err(err) if err.is_not_found() => {}
+                res => {
+                    res?;
+                    cgroup_common::signal_all(
+                        &self
+                            .get_path_for_cgroup_legacy_subsystem("freezer", group_id)
+                            .join("tasks"),
+                    )?;
+                    self.write_str(group_id, "freezer", "freezer.state", "THAWED")?;
+                }
+            }
+        }
+        for subsys in self.distinct_controllers() {
+            cgroup_common::signal_all(
+                &self
+                    .get_path_for_cgroup_legacy_subsystem(subsys, group_id)
+                    .join("tasks"),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn wait_group_empty(&self, group_id: &str, timeout: Duration) -> Result<bool, Self::Error> {
+        // cgroup v1 does not notify when cgroup becomes empty, so task
+        // lists are polled. Processes which were forked while others were
+        // being killed are killed as well.
+        let deadline = Instant::now() + timeout;
+        loop {
+            let mut populated = false;
+            for subsys in self.distinct_controllers() {
+                populated |= cgroup_common::signal_all(
+                    &self
+                        .get_path_for_cgroup_legacy_subsystem(subsys, group_id)
+                        .join("tasks"),
+                )?;
+            }
+            if !populated {
+                return Ok(true);
+            }
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+            std::thread::sleep(EMPTY_POLL_INTERVAL);
+        }
+    }
+
+    fn cleanup_stale_groups(&self) -> Result<Vec<String>, Self::Error> {
+        let prefix_path = self.get_prefix_for_subsystem("pids");
+        let _prefix_lock = match cgroup_common::lock_dir(&prefix_path, FlockArg::LockExclusive) {
+            Err(err) if err.is_not_found() => return Ok(Vec::new()),
+            res => res?,
+        };
+        // group can be left in some hierarchies only, e.g. if its creation failed
+        let mut groups = BTreeSet::new();
+        for subsys in self.distinct_controllers() {
+            groups.extend(cgroup_common::list_groups(
+                &self.get_prefix_for_subsystem(subsys),
+            )?);
+        }
+        let mut stale = Vec::new();
+        for group_id in groups {
+            let pids_path = self.get_path_for_cgroup_legacy_subsystem("pids", &group_id);
+            if cgroup_common::is_locked(&pids_path)? {
+                continue;
+            }
+            self.kill_group(&group_id)?;
+            if !self.wait_group_empty(&group_id, cgroup_common::KILL_TIMEOUT)? {
+                return Err(CgroupError::Kill { path: pids_path });
+            }
+            self.drop_cgroup(&group_id);
+            stale.push(group_id);
+        }
+        Ok(stale)
+    }
 }
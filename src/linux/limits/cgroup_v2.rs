@@ -1,21 +1,141 @@
 //! Implements Cgroup Driver for V2 cgroups
 use crate::linux::limits::{
-    cgroup_common::{CgroupEnter, CgroupError},
-    InternalResourceUsageData, ResourceLimitImpl, ResourceLimits,
+    cgroup_common::{self, CgroupEnter, CgroupError},
+    cpu_list, InternalResourceUsageData, ResourceLimitImpl, ResourceLimits, SwapPolicy,
 };
-use std::{ffi::OsString, os::unix::io::IntoRawFd, path::PathBuf};
+use crate::{linux::fd::Fd, MemoryBreakdown};
+use nix::{
+    fcntl::FlockArg,
+    sys::inotify::{AddWatchFlags, InitFlags, Inotify},
+};
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::File,
+    os::unix::io::{AsRawFd, IntoRawFd},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// Leaf cgroup for the current process, created in the delegated cgroup
+const HOST_LEAF: &str = "minion-host";
+
+/// Controllers which are enabled in the delegated cgroup, if available
+const CONTROLLERS: &[&str] = &["cpu", "cpuset", "io", "memory", "pids"];
+
+/// Returns cgroup of the current process, relative to cgroupfs root.
+fn current_cgroup() -> Result<PathBuf, CgroupError> {
+    let proc_path = PathBuf::from("/proc/self/cgroup");
+    let data = std::fs::read_to_string(&proc_path).map_err(|cause| CgroupError::Read {
+        path: proc_path,
+        cause,
+    })?;
+    let current = data
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or(CgroupError::UnknownCurrentCgroup)?;
+    Ok(PathBuf::from(current.trim_start_matches('/')))
+}
+
+/// Finds cgroup of the current process (which should be delegated to
+/// the current user, e.g. systemd unit with `Delegate=yes`) and checks
+/// that it can be managed. Nothing is changed, so this can be used
+/// while probing drivers.
+/// Returns path to the delegated cgroup, relative to `cgroupfs_path`.
+pub(super) fn find_delegated_root(cgroupfs_path: &Path) -> Result<PathBuf, CgroupError> {
+    let mut delegated = current_cgroup()?;
+    // already moved, e.g. by other backend instance
+    if delegated
+        .file_name()
+        .map_or(false, |name| name == HOST_LEAF)
+    {
+        delegated.pop();
+    }
+    for file in &["cgroup.procs", "cgroup.subtree_control"] {
+        let path = cgroupfs_path.join(&delegated).join(file);
+        nix::unistd::access(&path, nix::unistd::AccessFlags::W_OK).map_err(|err| {
+            CgroupError::Write {
+                path,
+                cause: crate::linux::util::cvt_error(err),
+            }
+        })?;
+    }
+    Ok(delegated)
+}
+
+/// Prepares delegated cgroup for creating sandbox cgroups in it.
+/// Cgroups with enabled controllers can not have processes, so
+/// current process is moved to a leaf child.
+fn enter_delegated_subtree(cgroupfs_path: &Path, delegated: &Path) -> Result<(), CgroupError> {
+    let delegated_path = cgroupfs_path.join(delegated);
+    let leaf_path = delegated_path.join(HOST_LEAF);
+    if current_cgroup()? != delegated.join(HOST_LEAF) {
+        tracing::info!(
+            cgroup = %leaf_path.display(),
+            "Moving current process to a leaf of the delegated cgroup"
+        );
+        std::fs::create_dir_all(&leaf_path).map_err(|cause| CgroupError::CreateCgroupDir {
+            path: leaf_path.clone(),
+            cause,
+        })?;
+        let procs_path = leaf_path.join("cgroup.procs");
+        std::fs::write(&procs_path, std::process::id().to_string()).map_err(|cause| {
+            CgroupError::Write {
+                path: procs_path,
+                cause,
+            }
+        })?;
+    }
+    let controllers_path = delegated_path.join("cgroup.controllers");
+    let available =
+        std::fs::read_to_string(&controllers_path).map_err(|cause| CgroupError::Read {
+            path: controllers_path,
+            cause,
+        })?;
+    let enable = available
+        .split_ascii_whitespace()
+        .filter(|controller| CONTROLLERS.contains(controller))
+        .map(|controller| format!("+{}", controller))
+        .collect::<Vec<_>>();
+    let subtree_control_path = delegated_path.join("cgroup.subtree_control");
+    std::fs::write(&subtree_control_path, enable.join(" ")).map_err(|cause| {
+        CgroupError::Write {
+            path: subtree_control_path,
+            cause,
+        }
+    })?;
+    Ok(())
+}
 
 #[derive(Debug)]
 pub(super) struct CgroupV2 {
     pub(super) cgroupfs_path: PathBuf,
     pub(super) cgroup_prefix: Vec<OsString>,
+    /// Locked directories of groups created by this instance.
+    /// See `cgroup_common::lock_dir`.
+    pub(super) locks: Mutex<HashMap<String, File>>,
+    /// Delegated cgroup (relative to `cgroupfs_path`) which should be
+    /// prepared by `prepare` before use.
+    pub(super) delegated_root: Option<PathBuf>,
 }
 
 impl CgroupV2 {
+    /// Called once this driver is selected.
+    pub(super) fn prepare(&self) -> Result<(), CgroupError> {
+        match &self.delegated_root {
+            Some(root) => enter_delegated_subtree(&self.cgroupfs_path, root),
+            None => Ok(()),
+        }
+    }
+
     fn write_file(&self, cgroup_id: &str, file_name: &str, num: u64) -> Result<(), CgroupError> {
-        let path = self.get_path_for_cgroup_unified(cgroup_id).join(file_name);
         let mut buf = itoa::Buffer::new();
-        let data = buf.format(num);
+        self.write_str(cgroup_id, file_name, buf.format(num))
+    }
+
+    fn write_str(&self, cgroup_id: &str, file_name: &str, data: &str) -> Result<(), CgroupError> {
+        let path = self.get_path_for_cgroup_unified(cgroup_id).join(file_name);
 
         std::fs::write(&path, data).map_err(|cause| CgroupError::Write { path, cause })
     }
@@ -26,22 +146,90 @@ impl CgroupV2 {
         std::fs::read_to_string(&path).map_err(|cause| CgroupError::Read { path, cause })
     }
 
-    fn get_cpu_usage(&self, cgroup_id: &str) -> Result<u64, CgroupError> {
+    /// Returns total, user and system CPU time in nanoseconds.
+    fn get_cpu_stat(
+        &self,
+        cgroup_id: &str,
+    ) -> Result<(u64, Option<u64>, Option<u64>), CgroupError> {
         let stat_data = self.read_file(cgroup_id, "cpu.stat")?;
-        let mut val = u64::max_value();
-        for line in stat_data.lines() {
-            if line.starts_with("usage_usec") {
-                let usage = line
-                    .trim_start_matches("usage_usec ")
-                    .trim_end_matches('\n');
-                if let Ok(v) = usage.parse() {
-                    val = v;
-                    // multiply by 1000 to convert from microseconds to nanoseconds
-                    val *= 1000;
-                }
+        // multiply by 1000 to convert from microseconds to nanoseconds
+        let nanos = |key| cgroup_common::flat_keyed_value(&stat_data, key).map(|usec| usec * 1000);
+        Ok((
+            nanos("usage_usec").unwrap_or_else(u64::max_value),
+            nanos("user_usec"),
+            nanos("system_usec"),
+        ))
+    }
+
+    /// `memory.peak` is only available since Linux 5.19
+    fn get_memory_peak(&self, cgroup_id: &str) -> Option<u64> {
+        cgroup_common::read_optional_value(
+            &self
+                .get_path_for_cgroup_unified(cgroup_id)
+                .join("memory.peak"),
+        )
+    }
+
+    /// Returns major and minor page faults
+    fn get_page_faults(&self, cgroup_id: &str) -> Result<(Option<u64>, Option<u64>), CgroupError> {
+        let stat_data = self.read_file(cgroup_id, "memory.stat")?;
+        let faults = cgroup_common::flat_keyed_value(&stat_data, "pgfault");
+        let major = cgroup_common::flat_keyed_value(&stat_data, "pgmajfault");
+        // pgfault includes major faults
+        let minor = faults
+            .zip(major)
+            .map(|(faults, major)| faults.saturating_sub(major));
+        Ok((major, minor))
+    }
+
+    fn get_current_memory_usage(&self, cgroup_id: &str) -> Result<u64, CgroupError> {
+        let usage = self
+            .read_file(cgroup_id, "memory.current")?
+            .trim()
+            .parse()
+            .unwrap();
+        Ok(usage)
+    }
+
+    fn get_memory_breakdown(&self, cgroup_id: &str) -> Result<MemoryBreakdown, CgroupError> {
+        let stat_data = self.read_file(cgroup_id, "memory.stat")?;
+        Ok(MemoryBreakdown {
+            anon: cgroup_common::flat_keyed_value(&stat_data, "anon").unwrap_or(0),
+            file: cgroup_common::flat_keyed_value(&stat_data, "file").unwrap_or(0),
+        })
+    }
+
+    /// Returns total bytes read and written, if I/O controller is enabled.
+    fn get_io_usage(&self, cgroup_id: &str) -> Option<(u64, u64)> {
+        let stat_data = self.read_file(cgroup_id, "io.stat").ok()?;
+        Some((
+            cgroup_common::sum_v2_io_stat(&stat_data, "rbytes"),
+            cgroup_common::sum_v2_io_stat(&stat_data, "wbytes"),
+        ))
+    }
+
+    /// Sends SIGKILL to all processes while the cgroup is frozen, so that
+    /// most of them can not create new processes meanwhile. Freezing is
+    /// not waited for, processes forked by not yet frozen ones are killed
+    /// by `wait_group_empty`.
+    fn freeze_and_kill(&self, cgroup_id: &str) -> Result<(), CgroupError> {
+        // cgroup.freeze is only available since Linux 5.2
+        let frozen = match self.write_str(cgroup_id, "cgroup.freeze", "1") {
+            Err(err) if err.is_not_found() => false,
+            res => {
+                res?;
+                true
             }
+        };
+        cgroup_common::signal_all(
+            &self
+                .get_path_for_cgroup_unified(cgroup_id)
+                .join("cgroup.procs"),
+        )?;
+        if frozen {
+            self.write_str(cgroup_id, "cgroup.freeze", "0")?;
         }
-        Ok(val)
+        Ok(())
     }
 
     fn drop_cgroup(&self, cgroup_id: &str) {
@@ -71,11 +259,21 @@ impl ResourceLimitImpl for CgroupV2 {
         group_id: &str,
         limits: &ResourceLimits,
     ) -> Result<Self::Enter, Self::Error> {
+        let prefix_path = self.get_cgroup_prefix();
+        std::fs::create_dir_all(&prefix_path).map_err(|cause| CgroupError::CreateCgroupDir {
+            path: prefix_path.clone(),
+            cause,
+        })?;
+        // prevents `cleanup_stale_groups` from seeing the group before it is locked
+        let _prefix_lock = cgroup_common::lock_dir(&prefix_path, FlockArg::LockShared)?;
         let cgroup_path = self.get_path_for_cgroup_unified(group_id);
         std::fs::create_dir_all(&cgroup_path).map_err(|cause| CgroupError::CreateCgroupDir {
             path: cgroup_path.clone(),
             cause,
         })?;
+        if let Some(lock) = cgroup_common::lock_dir(&cgroup_path, FlockArg::LockExclusive)? {
+            self.locks.lock().insert(group_id.to_string(), lock);
+        }
 
         // TODO: should we ignore this error?
         std::fs::write(
@@ -86,6 +284,63 @@ impl ResourceLimitImpl for CgroupV2 {
 
         self.write_file(group_id, "pids.max", limits.pids_max.into())?;
         self.write_file(group_id, "memory.max", limits.memory_max)?;
+        // memory.swap.max is only available if swap accounting is enabled
+        match limits.swap {
+            SwapPolicy::Deny => match self.write_file(group_id, "memory.swap.max", 0) {
+                Err(err) if err.is_not_found() => {
+                    tracing::warn!("swap accounting is disabled, swap usage is not restricted")
+                }
+                res => res?,
+            },
+            SwapPolicy::Limit(swap_max) => {
+                self.write_file(group_id, "memory.swap.max", swap_max)?
+            }
+            SwapPolicy::Unrestricted => {}
+        }
+        if let Some(memory_high) = limits.memory_high {
+            self.write_file(group_id, "memory.high", memory_high)?;
+        }
+        if let Some((quota, period)) = limits.cpu_max() {
+            self.write_str(group_id, "cpu.max", &format!("{} {}", quota, period))?;
+        }
+        // io controller is optional, because it is not always available.
+        // Without it, limits can not be applied and counters are not reported.
+        std::fs::write(
+            cgroup_path.parent().unwrap().join("cgroup.subtree_control"),
+            "+io",
+        )
+        .ok();
+        for limit in &limits.io_limits {
+            let mut line = cgroup_common::device_number(&limit.device)?;
+            let fields = [
+                ("rbps", limit.read_bps),
+                ("wbps", limit.write_bps),
+                ("riops", limit.read_iops),
+                ("wiops", limit.write_iops),
+            ];
+            for (key, value) in &fields {
+                if let Some(value) = value {
+                    line.push_str(&format!(" {}={}", key, value));
+                }
+            }
+            self.write_str(group_id, "io.max", &line)?;
+        }
+        if let Some(cpuset) = &limits.cpuset {
+            // cpuset controller is not enabled above, because it is not
+            // always available.
+            std::fs::write(
+                cgroup_path.parent().unwrap().join("cgroup.subtree_control"),
+                "+cpuset",
+            )
+            .ok();
+            // empty list means that parent's list is used
+            if !cpuset.cpus.is_empty() {
+                self.write_str(group_id, "cpuset.cpus", &cpu_list(&cpuset.cpus))?;
+            }
+            if !cpuset.mems.is_empty() {
+                self.write_str(group_id, "cpuset.mems", &cpu_list(&cpuset.mems))?;
+            }
+        }
 
         let tasks_file_path = cgroup_path.join("cgroup.procs");
         let h = std::fs::OpenOptions::new()
@@ -101,14 +356,101 @@ impl ResourceLimitImpl for CgroupV2 {
     }
 
     fn resource_usage(&self, group_id: &str) -> Result<InternalResourceUsageData, Self::Error> {
+        let io = self.get_io_usage(group_id);
+        let (time, user_time, system_time) = self.get_cpu_stat(group_id)?;
+        let (major_page_faults, minor_page_faults) = self.get_page_faults(group_id)?;
         Ok(InternalResourceUsageData {
-            time: self.get_cpu_usage(group_id)?,
-            memory: None,
+            time,
+            user_time,
+            system_time,
+            memory: self.get_memory_peak(group_id),
+            memory_current: Some(self.get_current_memory_usage(group_id)?),
+            memory_breakdown: Some(self.get_memory_breakdown(group_id)?),
+            io_read: io.map(|(read, _)| read),
+            io_write: io.map(|(_, write)| write),
+            major_page_faults,
+            minor_page_faults,
+            voluntary_context_switches: None,
+            involuntary_context_switches: None,
+            pids_peak: cgroup_common::read_optional_value(
+                &self.get_path_for_cgroup_unified(group_id).join("pids.peak"),
+            ),
         })
     }
 
+    fn pids_limit_hit(&self, group_id: &str) -> Result<bool, Self::Error> {
+        let events = self.read_file(group_id, "pids.events")?;
+        Ok(cgroup_common::flat_keyed_value(&events, "max").unwrap_or(0) > 0)
+    }
+
+    fn memory_limit_hit(&self, group_id: &str) -> Result<bool, Self::Error> {
+        let events = self.read_file(group_id, "memory.events")?;
+        Ok(cgroup_common::flat_keyed_value(&events, "oom_kill").unwrap_or(0) > 0)
+    }
+
     fn delete_group(&self, group_id: &str) -> Result<(), Self::Error> {
         self.drop_cgroup(group_id);
+        self.locks.lock().remove(group_id);
         Ok(())
     }
+
+    fn kill_group(&self, group_id: &str) -> Result<(), Self::Error> {
+        // cgroup.kill is only available since Linux 5.14
+        match self.write_str(group_id, "cgroup.kill", "1") {
+            Err(err) if err.is_not_found() => self.freeze_and_kill(group_id),
+            res => res,
+        }
+    }
+
+    fn wait_group_empty(&self, group_id: &str, timeout: Duration) -> Result<bool, Self::Error> {
+        let deadline = Instant::now() + timeout;
+        let group_path = self.get_path_for_cgroup_unified(group_id);
+        // cgroup.events is modified when `populated` changes
+        let inotify = Inotify::init(InitFlags::IN_CLOEXEC | InitFlags::IN_NONBLOCK)
+            .map_err(|cause| CgroupError::Wait { cause })?;
+        let inotify_fd = Fd::new(inotify.as_raw_fd());
+        let events_path = group_path.join("cgroup.events");
+        match inotify.add_watch(&events_path, AddWatchFlags::IN_MODIFY) {
+            Err(nix::Error::Sys(nix::errno::Errno::ENOENT)) => return Ok(true),
+            res => res.map_err(|cause| CgroupError::Wait { cause })?,
+        };
+        loop {
+            let events = self.read_file(group_id, "cgroup.events")?;
+            if cgroup_common::flat_keyed_value(&events, "populated") == Some(0) {
+                return Ok(true);
+            }
+            // without cgroup.kill processes can fork while others are killed
+            cgroup_common::signal_all(&group_path.join("cgroup.procs"))?;
+            if !cgroup_common::wait_readable(inotify_fd.as_raw(), deadline)? {
+                return Ok(false);
+            }
+            match inotify.read_events() {
+                Ok(_) | Err(nix::Error::Sys(nix::errno::Errno::EAGAIN)) => {}
+                Err(cause) => return Err(CgroupError::Wait { cause }),
+            }
+        }
+    }
+
+    fn cleanup_stale_groups(&self) -> Result<Vec<String>, Self::Error> {
+        let prefix_path = self.get_cgroup_prefix();
+        let _prefix_lock = match cgroup_common::lock_dir(&prefix_path, FlockArg::LockExclusive) {
+            Err(err) if err.is_not_found() => return Ok(Vec::new()),
+            res => res?,
+        };
+        let mut stale = Vec::new();
+        for group_id in cgroup_common::list_groups(&prefix_path)? {
+            if cgroup_common::is_locked(&self.get_path_for_cgroup_unified(&group_id))? {
+                continue;
+            }
+            self.kill_group(&group_id)?;
+            if !self.wait_group_empty(&group_id, cgroup_common::KILL_TIMEOUT)? {
+                return Err(CgroupError::Kill {
+                    path: self.get_path_for_cgroup_unified(&group_id),
+                });
+            }
+            self.drop_cgroup(&group_id);
+            stale.push(group_id);
+        }
+        Ok(stale)
+    }
 }
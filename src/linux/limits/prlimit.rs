@@ -1,26 +1,46 @@
 use crate::linux::{
+    fd::Fd,
     ipc::IpcError,
     jail_common::{Query, ResourceUsageInformation, ZygoteInfo},
-    limits::{EnterHandle, InternalResourceUsageData, ResourceLimitImpl, ResourceLimits},
-    Error,
+    limits::{
+        cgroup_common, EnterHandle, InternalResourceUsageData, ResourceLimitImpl, ResourceLimits,
+        SwapPolicy,
+    },
+    util::Pid,
+    Error, MemoryRlimit,
 };
+use crate::MemoryBreakdown;
+use nix::poll::{PollFd, PollFlags};
 use parking_lot::Mutex;
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 #[derive(Debug)]
 pub(super) struct Group {
     zygote: Option<Arc<Mutex<Option<ZygoteInfo>>>>,
+    /// Zygote is init of the sandbox PID namespace, so it only exits
+    /// after all other sandbox processes are killed. `None` if pidfd
+    /// is not supported.
+    zygote_pidfd: Option<Fd>,
 }
 
 #[derive(Debug)]
 pub(super) struct Prlimit {
     pub(super) groups: Mutex<HashMap<String, Group>>,
     pub(super) allow_multiple_processes: bool,
+    pub(super) memory_rlimit: MemoryRlimit,
+    /// If set, resident memory of sandbox processes is collected
+    /// so that watchdog can enforce memory limit.
+    pub(super) rss_watchdog: bool,
 }
 
 #[derive(Clone)]
 pub(in crate::linux) struct PrlimitEnter {
     limits: ResourceLimits,
+    memory_rlimit: MemoryRlimit,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -29,8 +49,133 @@ pub enum PrlimitError {
     Ipc(#[from] IpcError),
     #[error("unable to correctly enforce pids>1 limit without root")]
     PidsLimitEnforcementImpossible,
+    #[error("CPU bandwidth can not be limited without cgroups")]
+    CpuBandwidthUnsupported,
+    #[error("memory.high can not be emulated without cgroups")]
+    MemoryHighUnsupported,
+    #[error("swap usage can not be limited without cgroups")]
+    SwapLimitUnsupported,
+    #[error("I/O can not be limited without cgroups")]
+    IoLimitUnsupported,
     #[error("sandbox is killed")]
     SandboxGone,
+    #[error("failed to read procfs")]
+    Procfs(#[source] std::io::Error),
+    #[error("failed to wait for sandbox processes")]
+    Wait(#[source] nix::Error),
+}
+
+/// Returns pids of all (transitive) children of `root`. Zygote is init of
+/// the sandbox PID namespace, so orphaned sandbox processes are its
+/// children as well, and only the process tree of the sandbox is visited.
+fn descendants(root: Pid) -> Result<Vec<Pid>, PrlimitError> {
+    // `children` files require CONFIG_PROC_CHILDREN
+    static PROC_CHILDREN_SUPPORTED: once_cell::sync::Lazy<bool> =
+        once_cell::sync::Lazy::new(|| std::path::Path::new("/proc/thread-self/children").exists());
+    if !*PROC_CHILDREN_SUPPORTED {
+        return scan_descendants(root);
+    }
+    let mut out = Vec::new();
+    let mut queue = vec![root];
+    while let Some(pid) = queue.pop() {
+        // process could have exited already
+        let tasks = match std::fs::read_dir(format!("/proc/{}/task", pid)) {
+            Ok(tasks) => tasks,
+            Err(_) => continue,
+        };
+        for task in tasks.filter_map(Result::ok) {
+            let children = match std::fs::read_to_string(task.path().join("children")) {
+                Ok(children) => children,
+                Err(_) => continue,
+            };
+            for child in children.split_ascii_whitespace() {
+                if let Ok(child) = child.parse::<Pid>() {
+                    out.push(child);
+                    queue.push(child);
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Returns pids of all (transitive) children of `root`, according
+/// to `/proc/<pid>/stat` of all processes on the host.
+fn scan_descendants(root: Pid) -> Result<Vec<Pid>, PrlimitError> {
+    let mut parents = Vec::new();
+    for entry in std::fs::read_dir("/proc").map_err(PrlimitError::Procfs)? {
+        let entry = entry.map_err(PrlimitError::Procfs)?;
+        let pid = match entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<Pid>().ok())
+        {
+            Some(pid) => pid,
+            None => continue,
+        };
+        // process could have exited already
+        let stat = match std::fs::read_to_string(entry.path().join("stat")) {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+        // comm can contain spaces and parentheses, so fields after
+        // last ')' are parsed: state, ppid, ...
+        let ppid = stat
+            .rfind(')')
+            .and_then(|pos| stat[pos + 1..].split_ascii_whitespace().nth(1))
+            .and_then(|ppid| ppid.parse::<Pid>().ok());
+        if let Some(ppid) = ppid {
+            parents.push((pid, ppid));
+        }
+    }
+    let mut found = HashSet::new();
+    found.insert(root);
+    let mut out = Vec::new();
+    // processes are not sorted by ancestry, so iterate until fixpoint
+    loop {
+        let prev_len = out.len();
+        for &(pid, ppid) in &parents {
+            if found.contains(&ppid) && found.insert(pid) {
+                out.push(pid);
+            }
+        }
+        if out.len() == prev_len {
+            break;
+        }
+    }
+    Ok(out)
+}
+
+/// Sums proportional set size (Pss) of given processes, in bytes. Pages
+/// shared by several processes are divided between them, so shared
+/// libraries and shared memory are counted once per group.
+fn resident_memory(pids: &[Pid]) -> MemoryBreakdown {
+    let mut usage = MemoryBreakdown::default();
+    for pid in pids {
+        // smaps_rollup is only available since Linux 4.14
+        let rollup = match std::fs::read_to_string(format!("/proc/{}/smaps_rollup", pid)) {
+            Ok(rollup) => rollup,
+            Err(_) => continue,
+        };
+        let field = |key| cgroup_common::flat_keyed_value(&rollup, key).map(|kib| kib * 1024);
+        match (field("Pss_Anon:"), field("Pss_File:"), field("Pss_Shmem:")) {
+            (Some(anon), Some(file), Some(shmem)) => {
+                usage.anon += anon;
+                // tmpfs and shared anonymous mappings are accounted as
+                // page cache
+                usage.file += file + shmem;
+            }
+            _ => {
+                // older kernels do not break Pss down, private anonymous
+                // pages are approximated by all anonymous ones
+                let pss = field("Pss:").unwrap_or(0);
+                let anon = field("Anonymous:").unwrap_or(0).min(pss);
+                usage.anon += anon;
+                usage.file += pss - anon;
+            }
+        }
+    }
+    usage
 }
 
 impl EnterHandle for PrlimitEnter {
@@ -45,7 +190,11 @@ impl EnterHandle for PrlimitEnter {
                 rlim_cur: self.limits.memory_max,
                 rlim_max: self.limits.memory_max,
             };
-            if libc::setrlimit(libc::RLIMIT_DATA, &lim) == -1 {
+            let resource = match self.memory_rlimit {
+                MemoryRlimit::Data => libc::RLIMIT_DATA,
+                MemoryRlimit::AddressSpace => libc::RLIMIT_AS,
+            };
+            if libc::setrlimit(resource, &lim) == -1 {
                 return Err(std::io::Error::last_os_error().into());
             }
 
@@ -53,9 +202,10 @@ impl EnterHandle for PrlimitEnter {
                 rlim_cur: self.limits.pids_max.into(),
                 rlim_max: self.limits.pids_max.into(),
             };
-            // NOTE: this is not process limit, but threads limit
-            // this must be paired with a seccomp policy that bans process
-            // creation.
+            // NOTE: this limits count of all tasks (including threads)
+            // of the sandbox uid. Without dedicated uid it is paired with
+            // a seccomp filter that bans process creation
+            // (see `Prlimit::needs_single_process`).
             if libc::setrlimit(libc::RLIMIT_NPROC, &lim) == -1 {
                 return Err(std::io::Error::last_os_error().into());
             }
@@ -74,10 +224,30 @@ impl EnterHandle for PrlimitEnter {
                 return Err(std::io::Error::last_os_error().into());
             }
         }
+        // NOTE: unlike cpuset cgroup, affinity can be changed by the
+        // sandboxed process itself, and memory nodes are not restricted.
+        if let Some(cpuset) = &self.limits.cpuset {
+            if !cpuset.cpus.is_empty() {
+                let mut set = nix::sched::CpuSet::new();
+                for &cpu in &cpuset.cpus {
+                    set.set(cpu as usize)?;
+                }
+                nix::sched::sched_setaffinity(nix::unistd::Pid::from_raw(0), &set)?;
+            }
+        }
         Ok(())
     }
 }
 
+impl Prlimit {
+    /// `RLIMIT_NPROC` only limits the sandbox when it has dedicated uid.
+    /// Otherwise only `pids_max == 1` is supported, and it is enforced
+    /// by denying process creation.
+    pub(super) fn needs_single_process(&self, limits: &ResourceLimits) -> bool {
+        !self.allow_multiple_processes && limits.pids_max <= 1
+    }
+}
+
 impl ResourceLimitImpl for Prlimit {
     type Error = PrlimitError;
 
@@ -91,13 +261,32 @@ impl ResourceLimitImpl for Prlimit {
         if limits.pids_max > 1 && !self.allow_multiple_processes {
             return Err(PrlimitError::PidsLimitEnforcementImpossible);
         }
-        let prev = self
-            .groups
-            .lock()
-            .insert(group_id.to_string(), Group { zygote: None });
+        if limits.cpu_bandwidth.is_some() {
+            return Err(PrlimitError::CpuBandwidthUnsupported);
+        }
+        // Deny does not need enforcement: memory rlimit limits virtual
+        // memory, so swap can not be used to exceed it. For the same
+        // reason additional swap can not be granted.
+        if let SwapPolicy::Limit(_) = limits.swap {
+            return Err(PrlimitError::SwapLimitUnsupported);
+        }
+        if limits.memory_high.is_some() {
+            return Err(PrlimitError::MemoryHighUnsupported);
+        }
+        if !limits.io_limits.is_empty() {
+            return Err(PrlimitError::IoLimitUnsupported);
+        }
+        let prev = self.groups.lock().insert(
+            group_id.to_string(),
+            Group {
+                zygote: None,
+                zygote_pidfd: None,
+            },
+        );
         assert!(prev.is_none());
         Ok(PrlimitEnter {
             limits: limits.clone(),
+            memory_rlimit: self.memory_rlimit,
         })
     }
 
@@ -112,6 +301,13 @@ impl ResourceLimitImpl for Prlimit {
         let group = groups
             .get_mut(group_id)
             .expect("register_group_details is called on unknown group_id");
+        if crate::linux::check::pidfd_supported() {
+            // zygote is alive until the sandbox is killed, so pid is not reused yet
+            let pid = z.lock().as_ref().map(|z| z.pid);
+            group.zygote_pidfd = pid
+                .and_then(|pid| crate::linux::util::pidfd_open(pid).ok())
+                .map(Fd::new);
+        }
         group.zygote = Some(z);
     }
 
@@ -121,14 +317,72 @@ impl ResourceLimitImpl for Prlimit {
             .get(group_id)
             .expect("resource_usage is called on unknown group_id");
         let zygote = group.zygote.clone().expect("zygotek not initialized yet");
-        let mut zygote = zygote.lock();
-        let zygote = zygote.as_mut().ok_or(PrlimitError::SandboxGone)?;
         drop(groups);
-        zygote.sock.send(&Query::GetResourceUsage)?;
-        let info: ResourceUsageInformation = zygote.sock.recv()?;
+        let (info, zygote_pid) = {
+            let mut zygote = zygote.lock();
+            let zygote = zygote.as_mut().ok_or(PrlimitError::SandboxGone)?;
+            zygote.sock.send(&Query::GetResourceUsage)?;
+            let info: ResourceUsageInformation = zygote.sock.recv()?;
+            (info, zygote.pid)
+        };
+        // procfs is read without holding zygote lock
+        let memory_breakdown = if self.rss_watchdog {
+            Some(resident_memory(&descendants(zygote_pid)?))
+        } else {
+            None
+        };
         Ok(InternalResourceUsageData {
             memory: Some(info.memory),
-            time: info.cpu,
+            memory_current: memory_breakdown.map(|usage| usage.anon + usage.file),
+            memory_breakdown,
+            time: info.user_time + info.system_time,
+            user_time: Some(info.user_time),
+            system_time: Some(info.system_time),
+            io_read: None,
+            io_write: None,
+            major_page_faults: Some(info.major_page_faults),
+            minor_page_faults: Some(info.minor_page_faults),
+            voluntary_context_switches: Some(info.voluntary_context_switches),
+            involuntary_context_switches: Some(info.involuntary_context_switches),
+            pids_peak: None,
         })
     }
+
+    /// Without pidfd support it only kills the zygote.
+    fn kill_group(&self, group_id: &str) -> Result<(), Self::Error> {
+        let groups = self.groups.lock();
+        let group = groups
+            .get(group_id)
+            .expect("kill_group is called on unknown group_id");
+        if let Some(pidfd) = &group.zygote_pidfd {
+            // zygote can be already killed
+            crate::linux::util::pidfd_send_signal(pidfd.as_raw(), libc::SIGKILL).ok();
+        }
+        Ok(())
+    }
+
+    /// Without pidfd support processes can not be waited for.
+    fn wait_group_empty(&self, group_id: &str, timeout: Duration) -> Result<bool, Self::Error> {
+        let groups = self.groups.lock();
+        let group = groups
+            .get(group_id)
+            .expect("wait_group_empty is called on unknown group_id");
+        let pidfd = match &group.zygote_pidfd {
+            Some(fd) => fd,
+            None => return Ok(true),
+        };
+        // pidfd becomes readable when zygote exits
+        let deadline = Instant::now() + timeout;
+        let mut poll_fds = [PollFd::new(pidfd.as_raw(), PollFlags::POLLIN)];
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+            match nix::poll::poll(&mut poll_fds, timeout_ms) {
+                Ok(0) => return Ok(false),
+                Ok(_) => return Ok(true),
+                Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+                Err(err) => return Err(PrlimitError::Wait(err)),
+            }
+        }
+    }
 }
@@ -1,25 +1,37 @@
+mod fs_tracker;
 mod watchdog;
 
-use self::watchdog::{watchdog, Event};
+use self::{
+    fs_tracker::FilesystemTracker,
+    watchdog::{watchdog, Event},
+};
 use crate::{
     linux::{
+        cpu_alloc::CpuAllocator,
         fd::Fd,
-        jail_common::{self, LinuxSharedItem, SharedItemFlags, ZygoteInfo},
+        jail_common::{self, LinuxSharedItem, MountPropagation, SharedItemFlags, ZygoteInfo},
         limits::ResourceLimits,
         uid_alloc::UidAllocator,
         util::Pid,
-        zygote, Error,
+        zygote, Error, SeccompPolicy,
     },
-    ExitCode, ResourceUsageData, Sandbox, SandboxOptions, SharedItem,
+    ExitCode, FilesystemChange, ResourceUsageData, Sandbox, SandboxOptions, SecurityViolation,
+    SharedItem,
 };
 use parking_lot::Mutex;
 use std::{
+    collections::BTreeSet,
     convert::TryInto,
     fmt::Debug,
+    fs::File,
+    io::{Read, Write},
+    os::unix::io::FromRawFd,
+    path::{Component, Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering::SeqCst},
         Arc,
     },
+    time::{Duration, Instant},
 };
 
 use super::seccomp::Seccomp;
@@ -32,6 +44,8 @@ struct SandboxState {
     was_cpu_tle: AtomicBool,
     /// Wall-clock time limit was exceeded
     was_wall_tle: AtomicBool,
+    /// Watchdog killed sandbox because of memory usage
+    was_mle: AtomicBool,
 }
 
 impl SandboxState {
@@ -43,11 +57,84 @@ impl SandboxState {
             Event::RealTle => {
                 self.was_wall_tle.store(true, SeqCst);
             }
+            Event::MemoryLimit => {
+                self.was_mle.store(true, SeqCst);
+            }
             Event::Heartbeat => {}
         }
     }
 }
 
+/// Resources allocated for a sandbox. They are returned to allocators
+/// when dropped, including when sandbox creation fails.
+#[derive(Debug, Default)]
+struct Allocations {
+    uid: Option<(Arc<UidAllocator>, u32)>,
+    cpu: Option<(Arc<CpuAllocator>, u32)>,
+}
+
+impl Drop for Allocations {
+    fn drop(&mut self) {
+        if let Some((uid_alloc, sandbox_uid)) = self.uid.take() {
+            tracing::debug!(uid = sandbox_uid, "Freeing sandbox_uid");
+            uid_alloc.deallocate(sandbox_uid);
+        }
+        if let Some((cpu_alloc, cpu)) = self.cpu.take() {
+            tracing::debug!(cpu, "Freeing dedicated CPU");
+            cpu_alloc.deallocate(cpu);
+        }
+    }
+}
+
+/// How long dropped sandbox waits for its processes to exit before
+/// UID and CPU are reclaimed.
+const DROP_KILL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Releases sandbox resources, which can only be released after all
+/// sandbox processes exit.
+struct Cleanup {
+    id: String,
+    driver: Arc<crate::linux::limits::Driver>,
+    allocations: Allocations,
+    /// Set if all sandbox processes are known to be dead.
+    exited: bool,
+}
+
+impl Cleanup {
+    fn wait(&mut self, timeout: Duration) {
+        match self.driver.wait_group_empty(&self.id, timeout) {
+            Ok(exited) => self.exited = exited,
+            Err(err) => tracing::error!("failed to wait for sandbox processes: {:#}", err),
+        }
+    }
+}
+
+impl Drop for Cleanup {
+    #[tracing::instrument(skip(self), fields(id = self.id.as_str()))]
+    fn drop(&mut self) {
+        let mut clean = self.exited;
+        if !clean {
+            tracing::error!("sandbox processes may still be running");
+        }
+        // Remove cgroups.
+        if std::env::var("MINION_DEBUG_KEEP_CGROUPS").is_err() {
+            if let Err(e) = self.driver.delete_group(&self.id) {
+                tracing::error!("failed to delete cgroup: {:#}", e);
+                clean = false;
+            }
+        }
+        // Reclaim UID and CPU only when no sandboxed process can be
+        // running, otherwise they could be handed out to another sandbox.
+        let allocations = std::mem::take(&mut self.allocations);
+        if clean {
+            drop(allocations);
+        } else {
+            tracing::warn!("sandbox was not cleaned up, UID and CPU are not reclaimed");
+            std::mem::forget(allocations);
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct LinuxSandbox {
@@ -57,7 +144,11 @@ pub struct LinuxSandbox {
     state: SandboxState,
     watchdog_chan: crossbeam_channel::Receiver<Event>,
     driver: Arc<crate::linux::limits::Driver>,
-    dealloc_uid: Option<(Arc<UidAllocator>, u32)>,
+    allocations: Allocations,
+    fs_tracker: Mutex<FilesystemTracker>,
+    /// Default policy for jobs
+    seccomp: Seccomp,
+    created: Instant,
 }
 
 impl Sandbox for LinuxSandbox {
@@ -77,11 +168,32 @@ impl Sandbox for LinuxSandbox {
         Ok(self.state.was_wall_tle.load(SeqCst))
     }
 
+    fn check_pids_limit_exceeded(&self) -> Result<bool, Error> {
+        Ok(self.driver.pids_limit_hit(&self.id)?)
+    }
+
+    fn check_memory_limit_exceeded(&self) -> Result<bool, Error> {
+        self.poll_state();
+        if self.state.was_mle.load(SeqCst) {
+            return Ok(true);
+        }
+        Ok(self.driver.memory_limit_hit(&self.id)?)
+    }
+
     fn kill(&self) -> Result<(), Error> {
+        // Killing zygote kills all processes in its PID namespace, and
+        // prevents new processes from being spawned.
         self.zygote.lock().take();
+        // Processes can escape the PID namespace, and namespace teardown
+        // is asynchronous, so cgroup is emptied as well.
+        self.driver.kill_group(&self.id)?;
         Ok(())
     }
 
+    fn wait_killed(&self, timeout: Duration) -> Result<bool, Error> {
+        Ok(self.driver.wait_group_empty(&self.id, timeout)?)
+    }
+
     fn debug_info(&self) -> Result<serde_json::Value, Self::Error> {
         let zygote_pid = self
             .with_zygote(|z| z.pid)
@@ -95,18 +207,143 @@ impl Sandbox for LinuxSandbox {
         let usage = self.driver.resource_usage(&self.id)?;
         Ok(ResourceUsageData {
             time: Some(usage.time),
-            memory: usage.memory,
+            user_time: usage.user_time,
+            system_time: usage.system_time,
+            wall_time: Some(
+                self.created
+                    .elapsed()
+                    .as_nanos()
+                    .try_into()
+                    .unwrap_or(u64::MAX),
+            ),
+            memory_peak: usage.memory,
+            memory_current: usage.memory_current,
+            memory_breakdown: usage.memory_breakdown,
+            io_read: usage.io_read,
+            io_write: usage.io_write,
+            major_page_faults: usage.major_page_faults,
+            minor_page_faults: usage.minor_page_faults,
+            voluntary_context_switches: usage.voluntary_context_switches,
+            involuntary_context_switches: usage.involuntary_context_switches,
+            pids_peak: usage.pids_peak,
+        })
+    }
+
+    fn filesystem_changes(&self) -> Result<Vec<FilesystemChange>, Error> {
+        self.fs_tracker.lock().changes()
+    }
+
+    fn expose(&self, item: SharedItem) -> Result<(), Error> {
+        // Bind target is resolved in the sandbox without following
+        // symlinks, which requires openat2.
+        if !crate::linux::check::new_mount_api_supported() {
+            return Err(Error::RequestFailed {
+                message: "exposing items in running sandbox requires new mount API".to_string(),
+            });
+        }
+        let mut item = convert_shared_item(item)?;
+        item.dest = child_path(&item.dest)?;
+        // Mount tree is created on the host, because zygote has no
+        // access to the host filesystem.
+        let tree =
+            crate::linux::zygote::mount_api::detached_tree(&item.src, &item.kind, &item.flags)?;
+        let q = jail_common::Query::Expose(item.clone());
+        self.with_zygote(|zyg| {
+            zyg.sock.send(&q)?;
+            zyg.sock.send_fds(&[tree])?;
+            let res: Result<(), String> = zyg.sock.recv()?;
+            res.map_err(|message| Error::RequestFailed { message })
         })
+        .unwrap_or(Err(Error::SandboxGone))?;
+        self.fs_tracker.lock().add_mount(&item)
+    }
+
+    fn unexpose(&self, dest: &Path) -> Result<(), Error> {
+        let dest = child_path(dest)?;
+        let q = jail_common::Query::Unexpose(jail_common::UnexposeQuery { dest: dest.clone() });
+        self.zygote_request(&q)?;
+        self.fs_tracker.lock().remove_mount(&dest);
+        Ok(())
+    }
+
+    fn write_file(&self, path: &Path, data: &[u8], mode: u32) -> Result<(), Error> {
+        let mut file = self.open_file(path, Some(mode))?;
+        file.write_all(data)?;
+        Ok(())
+    }
+
+    fn security_violations(&self) -> Result<Vec<SecurityViolation>, Error> {
+        self.task_security_violations(None)
     }
+
+    fn used_syscalls(&self) -> Result<BTreeSet<i64>, Error> {
+        self.with_zygote(|zyg| {
+            zyg.sock.send(&jail_common::Query::GetUsedSyscalls)?;
+            Ok(zyg.sock.recv()?)
+        })
+        .unwrap_or(Err(Error::SandboxGone))
+    }
+
+    fn read_file(&self, path: &Path, max_len: u64) -> Result<Vec<u8>, Error> {
+        let file = self.open_file(path, None)?;
+        let mut data = Vec::new();
+        file.take(max_len).read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// Converts path for child to the form used in `LinuxSharedItem`.
+fn child_path(path: &Path) -> Result<PathBuf, Error> {
+    let mut res = PathBuf::new();
+    for comp in path.components() {
+        match comp {
+            Component::Normal(name) => res.push(name),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => {
+                return Err(Error::RequestFailed {
+                    message: format!("invalid path for child: {}", path.display()),
+                })
+            }
+        }
+    }
+    if res.as_os_str().is_empty() {
+        return Err(Error::RequestFailed {
+            message: "path for child must not refer to sandbox root".to_string(),
+        });
+    }
+    Ok(res)
 }
 
 fn convert_shared_item(item: SharedItem) -> Result<LinuxSharedItem, Error> {
-    let mut flags = SharedItemFlags { recursive: false };
+    let mut flags = SharedItemFlags::default();
     for f in &item.flags {
         match f.as_str() {
             "recursive" => {
                 flags.recursive = true;
             }
+            "noexec" => {
+                flags.noexec = true;
+            }
+            "nosuid" => {
+                flags.nosuid = true;
+            }
+            "nodev" => {
+                flags.nodev = true;
+            }
+            "noatime" => {
+                flags.noatime = true;
+            }
+            "private" | "slave" => {
+                let propagation = if f == "private" {
+                    MountPropagation::Private
+                } else {
+                    MountPropagation::Slave
+                };
+                // at most one propagation type can be specified
+                if flags.propagation.replace(propagation).is_some() {
+                    return Err(Error::InvalidSharedItemFlag { flag: f.clone() });
+                }
+            }
             _ => return Err(Error::InvalidSharedItemFlag { flag: f.clone() }),
         }
     }
@@ -134,6 +371,12 @@ impl LinuxSandbox {
         }
     }
 
+    /// Returns policy for job which should additionally be restricted
+    /// by `policy`.
+    pub(in crate::linux) fn job_seccomp(&self, policy: &SeccompPolicy) -> Result<Seccomp, Error> {
+        Ok(self.seccomp.restrict(Seccomp::new(policy)?)?)
+    }
+
     fn with_zygote<R>(&self, f: impl FnOnce(&mut ZygoteInfo) -> R) -> Option<R> {
         let mut z = self.zygote.lock();
         let z = &mut *z;
@@ -145,7 +388,9 @@ impl LinuxSandbox {
         settings: &crate::linux::Settings,
         driver: Arc<crate::linux::limits::Driver>,
         uid_alloc: Arc<UidAllocator>,
+        cpu_alloc: Arc<CpuAllocator>,
     ) -> Result<LinuxSandbox, Error> {
+        check_options(&options)?;
         let jail_id = jail_common::gen_jail_id();
 
         let shared_items = options
@@ -155,16 +400,56 @@ impl LinuxSandbox {
             .map(convert_shared_item)
             .collect::<Result<Vec<_>, _>>()?;
 
+        let mut allocations = Allocations::default();
+
         let sandbox_uid = if settings.rootless {
             None
         } else {
             let uid = uid_alloc.allocate().ok_or(Error::UidExhausted)?;
+            allocations.uid = Some((uid_alloc, uid));
 
             tracing::debug!(uid, "Allocated sandbox_uid");
             Some(uid)
         };
 
-        let seccomp = Seccomp::new(&settings.seccomp);
+        let dedicated_cpu = if options.cpuset.is_none() && cpu_alloc.is_enabled() {
+            let cpu = cpu_alloc.allocate().ok_or(Error::CpusExhausted)?;
+            allocations.cpu = Some((cpu_alloc, cpu));
+
+            tracing::debug!(cpu, "Allocated dedicated CPU");
+            Some(cpu)
+        } else {
+            None
+        };
+        let cpuset = match dedicated_cpu {
+            Some(cpu) => Some(crate::Cpuset {
+                cpus: vec![cpu],
+                mems: Vec::new(),
+            }),
+            None => options.cpuset.clone(),
+        };
+
+        let limits = ResourceLimits {
+            pids_max: options.max_alive_process_count,
+            memory_max: options.memory_limit,
+            memory_high: options.memory_high,
+            swap: options.swap,
+            cpu_usage: options
+                .cpu_time_limit
+                .as_nanos()
+                .try_into()
+                .expect("too big CPU time limit"),
+            cpu_bandwidth: options.cpu_bandwidth,
+            cpuset,
+            io_limits: options.io_limits.clone(),
+        };
+
+        let mut seccomp = Seccomp::new(&settings.seccomp)?;
+        if driver.needs_single_process(&limits) {
+            seccomp.deny_process_creation()?;
+        }
+
+        let fs_tracker = FilesystemTracker::new(&options.isolation_root, &shared_items)?;
 
         let jail_options = jail_common::JailOptions {
             max_alive_process_count: options.max_alive_process_count,
@@ -177,21 +462,10 @@ impl LinuxSandbox {
             allow_mount_ns_failure: settings.allow_unsupported_mount_namespace,
             sandbox_uid,
             enable_watchdog: driver.get_watchdog(),
-            seccomp,
+            seccomp: seccomp.clone(),
         };
 
-        let resource_group_enter_handle = driver.create_group(
-            &jail_options.jail_id,
-            &ResourceLimits {
-                pids_max: jail_options.max_alive_process_count,
-                memory_max: jail_options.memory_limit,
-                cpu_usage: jail_options
-                    .cpu_time_limit
-                    .as_nanos()
-                    .try_into()
-                    .expect("too big CPU time limit"),
-            },
-        )?;
+        let resource_group_enter_handle = driver.create_group(&jail_options.jail_id, &limits)?;
 
         let startup_info = zygote::start_zygote(jail_options, &resource_group_enter_handle)?;
 
@@ -209,10 +483,14 @@ impl LinuxSandbox {
             state: SandboxState {
                 was_cpu_tle: AtomicBool::new(false),
                 was_wall_tle: AtomicBool::new(false),
+                was_mle: AtomicBool::new(false),
             },
             watchdog_chan: watchdog_rx,
             driver: driver.clone(),
-            dealloc_uid: sandbox_uid.map(|sandbox_uid| (uid_alloc, sandbox_uid)),
+            allocations,
+            fs_tracker: Mutex::new(fs_tracker),
+            seccomp,
+            created: Instant::now(),
         };
         tokio::task::spawn(watchdog(
             jail_id,
@@ -226,6 +504,11 @@ impl LinuxSandbox {
                 .as_nanos()
                 .try_into()
                 .expect("too big real time limit"),
+            if driver.needs_memory_watchdog() {
+                Some(options.memory_limit)
+            } else {
+                None
+            },
             watchdog_tx,
             driver,
             sandbox.zygote.clone(),
@@ -256,6 +539,34 @@ impl LinuxSandbox {
         .unwrap_or(Err(Error::SandboxGone))
     }
 
+    /// Sends query, which zygote answers with `Result<(), String>`.
+    fn zygote_request(&self, q: &jail_common::Query) -> Result<(), Error> {
+        self.with_zygote(|zyg| {
+            zyg.sock.send(q)?;
+            let res: Result<(), String> = zyg.sock.recv()?;
+            res.map_err(|message| Error::RequestFailed { message })
+        })
+        .unwrap_or(Err(Error::SandboxGone))
+    }
+
+    fn open_file(&self, path: &Path, create_mode: Option<u32>) -> Result<File, Error> {
+        let q = jail_common::Query::OpenFile(jail_common::OpenFileQuery {
+            path: child_path(path)?,
+            create_mode,
+        });
+        let fd = self
+            .with_zygote(|zyg| {
+                zyg.sock.send(&q)?;
+                let res: Result<(), String> = zyg.sock.recv()?;
+                res.map_err(|message| Error::RequestFailed { message })?;
+                let fd = zyg.sock.recv_fds(1)?;
+                Ok(fd.into_iter().next().unwrap())
+            })
+            .unwrap_or(Err(Error::SandboxGone))?;
+        // Safety: descriptor is owned
+        Ok(unsafe { File::from_raw_fd(fd.into_raw()) })
+    }
+
     pub(crate) fn get_exit_code(&self, pid: Pid) -> ExitCode {
         self.with_zygote(|zyg| {
             let q = jail_common::Query::GetExitCode(jail_common::GetExitCodeQuery { pid });
@@ -267,24 +578,80 @@ impl LinuxSandbox {
         })
         .unwrap_or(ExitCode::KILLED)
     }
+
+    /// Returns violations made by task `pid` and its descendants,
+    /// or by all processes if `pid` is None.
+    pub(crate) fn task_security_violations(
+        &self,
+        pid: Option<Pid>,
+    ) -> Result<Vec<SecurityViolation>, Error> {
+        self.with_zygote(|zyg| {
+            zyg.sock
+                .send(&jail_common::Query::GetSecurityViolations(pid))?;
+            Ok(zyg.sock.recv()?)
+        })
+        .unwrap_or(Err(Error::SandboxGone))
+    }
+}
+
+/// Validates options that are not checked by the kernel in a way
+/// that produces a meaningful error.
+fn check_options(options: &SandboxOptions) -> Result<(), Error> {
+    if let Some(memory_high) = options.memory_high {
+        if memory_high > options.memory_limit {
+            return Err(Error::InvalidOptions {
+                message: format!(
+                    "memory_high ({}) exceeds memory_limit ({})",
+                    memory_high, options.memory_limit
+                ),
+            });
+        }
+    }
+    if let Some(bw) = options.cpu_bandwidth {
+        let min = Duration::from_millis(1);
+        let max = Duration::from_secs(1);
+        if bw.period < min || bw.period > max {
+            return Err(Error::InvalidOptions {
+                message: format!(
+                    "CPU bandwidth period {:?} is not between 1ms and 1s",
+                    bw.period
+                ),
+            });
+        }
+        if bw.quota < min {
+            return Err(Error::InvalidOptions {
+                message: format!("CPU bandwidth quota {:?} is less than 1ms", bw.quota),
+            });
+        }
+    }
+    Ok(())
 }
 
 impl Drop for LinuxSandbox {
     #[tracing::instrument(skip(self), fields(id = self.id.as_str()))]
     fn drop(&mut self) {
-        // Reclaim UID
-        if let Some((uid_alloc, sandbox_uid)) = self.dealloc_uid.take() {
-            tracing::debug!(uid = sandbox_uid, "Freeing sandbox_uid");
-            uid_alloc.deallocate(sandbox_uid);
-        }
-        // Kill all processes.
+        let mut cleanup = Cleanup {
+            id: self.id.clone(),
+            driver: self.driver.clone(),
+            allocations: std::mem::take(&mut self.allocations),
+            exited: false,
+        };
         if let Err(err) = self.kill() {
             tracing::error!("unable to kill sandbox: {}", err);
+            return;
         }
-        // Remove cgroups.
-        if std::env::var("MINION_DEBUG_KEEP_CGROUPS").is_err() {
-            if let Err(e) = self.driver.delete_group(&self.id) {
-                tracing::error!("failed to delete cgroup: {:#}", e);
+        // Killed processes usually exit at once. Otherwise they are waited
+        // for in background, so that dropping sandbox does not block.
+        cleanup.wait(Duration::from_secs(0));
+        if !cleanup.exited {
+            let res = std::thread::Builder::new()
+                .name("minion-sandbox-cleanup".to_string())
+                .spawn(move || {
+                    let mut cleanup = cleanup;
+                    cleanup.wait(DROP_KILL_TIMEOUT);
+                });
+            if let Err(err) = res {
+                tracing::error!("failed to spawn cleanup thread: {}", err);
             }
         }
     }
@@ -0,0 +1,247 @@
+//! Implements tracking of filesystem changes made by sandboxed processes.
+use crate::{
+    linux::{jail_common::LinuxSharedItem, Error},
+    FilesystemChange, FilesystemChangeKind, SharedItemKind,
+};
+use nix::{
+    dir::Dir,
+    fcntl::{AtFlags, OFlag},
+    sys::stat::{fstatat, FileStat, Mode, SFlag},
+};
+use std::{
+    collections::BTreeMap,
+    ffi::{CString, OsStr},
+    os::unix::{ffi::OsStrExt, io::AsRawFd},
+    path::{Path, PathBuf},
+};
+
+/// Maximum nesting of directories that is walked.
+const MAX_DEPTH: usize = 128;
+/// Maximum number of entries in one snapshot.
+const MAX_ENTRIES: usize = 100_000;
+
+/// Metadata that is compared to detect modifications
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct EntryInfo {
+    is_dir: bool,
+    size: u64,
+    ino: u64,
+    mode: u32,
+    mtime: (i64, i64),
+    ctime: (i64, i64),
+}
+
+impl EntryInfo {
+    fn new(stat: &FileStat) -> Self {
+        EntryInfo {
+            is_dir: is_dir(stat),
+            size: stat.st_size as u64,
+            ino: stat.st_ino,
+            mode: stat.st_mode,
+            mtime: (stat.st_mtime, stat.st_mtime_nsec),
+            ctime: (stat.st_ctime, stat.st_ctime_nsec),
+        }
+    }
+
+    fn is_modified(&self, old: &EntryInfo) -> bool {
+        if self.is_dir && old.is_dir {
+            // directory timestamps change every time an entry is added
+            // or removed, and these entries are reported on their own.
+            return self.ino != old.ino || self.mode != old.mode;
+        }
+        self != old
+    }
+}
+
+/// Remembers state of writable areas at sandbox creation and
+/// compares it with current state on request.
+pub(super) struct FilesystemTracker {
+    /// Pairs of (path on system, path for child) that should be walked.
+    roots: Vec<(PathBuf, PathBuf)>,
+    /// Paths for child that are mount targets. They are created by zygote
+    /// and are covered by their own roots (if writable), so they are never
+    /// reported.
+    mount_points: Vec<PathBuf>,
+    initial: BTreeMap<PathBuf, EntryInfo>,
+}
+
+impl std::fmt::Debug for FilesystemTracker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FilesystemTracker")
+            .field("roots", &self.roots)
+            .field("initial", &format_args!("{} entries", self.initial.len()))
+            .finish()
+    }
+}
+
+impl FilesystemTracker {
+    /// Takes initial snapshot. Must be called before zygote is started.
+    pub(super) fn new(
+        isolation_root: &Path,
+        shared_items: &[LinuxSharedItem],
+    ) -> Result<Self, Error> {
+        let mut roots = vec![(isolation_root.to_path_buf(), PathBuf::new())];
+        let mut mount_points = vec![PathBuf::from("proc")];
+        for item in shared_items {
+            mount_points.push(item.dest.clone());
+            if let SharedItemKind::Full = item.kind {
+                roots.push((item.src.clone(), item.dest.clone()));
+            }
+        }
+        let mut tracker = FilesystemTracker {
+            roots,
+            mount_points,
+            initial: BTreeMap::new(),
+        };
+        tracker.initial = tracker.snapshot()?;
+        Ok(tracker)
+    }
+
+    /// Registers item exposed after sandbox creation.
+    pub(super) fn add_mount(&mut self, item: &LinuxSharedItem) -> Result<(), Error> {
+        if !self.mount_points.contains(&item.dest) {
+            self.mount_points.push(item.dest.clone());
+        }
+        // entries that were hidden by the new mount are not changes
+        self.initial.retain(|path, _| !path.starts_with(&item.dest));
+        if let SharedItemKind::Full = item.kind {
+            let mut entries = BTreeMap::new();
+            self.walk(&item.src, &item.dest, &mut entries)?;
+            self.initial.extend(entries);
+            self.roots.push((item.src.clone(), item.dest.clone()));
+        }
+        Ok(())
+    }
+
+    /// Unregisters item that was unexposed.
+    /// Mount point itself is still ignored, because it was created by zygote.
+    pub(super) fn remove_mount(&mut self, dest: &Path) {
+        self.roots.retain(|(_, child_path)| child_path != dest);
+        self.initial.retain(|path, _| !path.starts_with(dest));
+    }
+
+    fn snapshot(&self) -> Result<BTreeMap<PathBuf, EntryInfo>, Error> {
+        let mut entries = BTreeMap::new();
+        for (host_path, child_path) in &self.roots {
+            self.walk(host_path, child_path, &mut entries)?;
+        }
+        Ok(entries)
+    }
+
+    /// Walks directory tree without recursion. Each directory is opened
+    /// relative to its parent with O_NOFOLLOW, so symlinks (including ones
+    /// swapped in by sandboxed processes during the walk) are never traversed.
+    fn walk(
+        &self,
+        host_dir: &Path,
+        child_dir: &Path,
+        out: &mut BTreeMap<PathBuf, EntryInfo>,
+    ) -> Result<(), Error> {
+        // Errors are ignored: sandboxed processes can remove entries
+        // or make them inaccessible while we are walking.
+        let root = match Dir::open(host_dir, dir_flags(), Mode::empty()) {
+            Ok(d) => d,
+            Err(_) => return Ok(()),
+        };
+        let mut stack = vec![Frame::new(root, child_dir.to_path_buf())];
+        while let Some(frame) = stack.last_mut() {
+            let name = match frame.names.pop() {
+                Some(n) => n,
+                None => {
+                    stack.pop();
+                    continue;
+                }
+            };
+            let child_path = frame.child_dir.join(OsStr::from_bytes(name.as_bytes()));
+            if self.mount_points.contains(&child_path) {
+                continue;
+            }
+            let parent_fd = frame.dir.as_raw_fd();
+            let stat = match fstatat(parent_fd, name.as_c_str(), AtFlags::AT_SYMLINK_NOFOLLOW) {
+                Ok(s) => s,
+                Err(_) => continue,
+            };
+            // parents of mount targets can be created by zygote
+            let is_mount_point_parent =
+                self.mount_points.iter().any(|p| p.starts_with(&child_path));
+            if !is_mount_point_parent {
+                if out.len() >= MAX_ENTRIES {
+                    return Err(Error::FilesystemTooLarge);
+                }
+                out.insert(child_path.clone(), EntryInfo::new(&stat));
+            }
+            if !is_dir(&stat) {
+                continue;
+            }
+            if stack.len() >= MAX_DEPTH {
+                return Err(Error::FilesystemTooLarge);
+            }
+            if let Ok(dir) = Dir::openat(parent_fd, name.as_c_str(), dir_flags(), Mode::empty()) {
+                stack.push(Frame::new(dir, child_path));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns changes since sandbox creation, sorted by path
+    pub(super) fn changes(&self) -> Result<Vec<FilesystemChange>, Error> {
+        let current = self.snapshot()?;
+        let mut changes = Vec::new();
+        for (path, info) in &current {
+            let kind = match self.initial.get(path) {
+                None => FilesystemChangeKind::Added,
+                Some(old) if info.is_modified(old) => FilesystemChangeKind::Modified,
+                Some(_) => continue,
+            };
+            changes.push(FilesystemChange {
+                path: Path::new("/").join(path),
+                kind,
+                old_size: self.initial.get(path).map(|old| old.size),
+                new_size: Some(info.size),
+            });
+        }
+        for (path, old) in &self.initial {
+            if !current.contains_key(path) {
+                changes.push(FilesystemChange {
+                    path: Path::new("/").join(path),
+                    kind: FilesystemChangeKind::Removed,
+                    old_size: Some(old.size),
+                    new_size: None,
+                });
+            }
+        }
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(changes)
+    }
+}
+
+/// Directory that is being walked, with entries that were not visited yet.
+struct Frame {
+    dir: Dir,
+    child_dir: PathBuf,
+    names: Vec<CString>,
+}
+
+impl Frame {
+    fn new(mut dir: Dir, child_dir: PathBuf) -> Self {
+        let names = dir
+            .iter()
+            .flatten()
+            .map(|entry| entry.file_name().to_owned())
+            .filter(|name| name.as_bytes() != b"." && name.as_bytes() != b"..")
+            .collect();
+        Frame {
+            dir,
+            child_dir,
+            names,
+        }
+    }
+}
+
+fn dir_flags() -> OFlag {
+    OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC
+}
+
+fn is_dir(stat: &FileStat) -> bool {
+    SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT == SFlag::S_IFDIR
+}
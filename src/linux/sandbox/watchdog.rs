@@ -10,16 +10,21 @@ use std::{
 pub(super) enum Event {
     CpuTle,
     RealTle,
+    MemoryLimit,
     Heartbeat,
 }
 
 /// Monitors a sandbox, kills processes which used all their CPU time limit.
 /// Limits are given in nanoseconds
-#[tracing::instrument(skip(cpu_time_limit, real_time_limit, chan, driver, zygote))]
+/// If `memory_limit` is set, sandbox is also killed when its current
+/// memory usage (as reported by the driver) exceeds it. Like with
+/// cgroup OOM killer, processes are killed by SIGKILL.
+#[tracing::instrument(skip(cpu_time_limit, real_time_limit, memory_limit, chan, driver, zygote))]
 pub(super) async fn watchdog(
     jail_id: String,
     cpu_time_limit: u64,
     real_time_limit: u64,
+    memory_limit: Option<u64>,
     chan: crossbeam_channel::Sender<Event>,
     driver: Arc<crate::linux::limits::Driver>,
     zygote: Arc<Mutex<Option<ZygoteInfo>>>,
@@ -50,13 +55,13 @@ pub(super) async fn watchdog(
             let _enter = span.enter();
             let elapsed = Instant::now().duration_since(start);
             let elapsed = elapsed.as_nanos() as u64;
-            let current_usage = driver
+            let (current_usage, current_memory) = driver
                 .resource_usage(&jail_id)
-                .map(|usage| usage.time)
+                .map(|usage| (usage.time, usage.memory_current))
                 .unwrap_or_else(|err| {
                     tracing::error!("failed to get time usage: {:?}", err);
                     tracing::error!("WARNING: assuming time limit exceeded");
-                    u64::max_value()
+                    (u64::max_value(), None)
                 });
             tracing::debug!(
                 cpu_usage = current_usage,
@@ -65,7 +70,11 @@ pub(super) async fn watchdog(
             );
             let was_cpu_tle = current_usage > cpu_time_limit;
             let was_real_tle = elapsed > real_time_limit;
-            let ok = !was_cpu_tle && !was_real_tle;
+            let was_mle = match (current_memory, memory_limit) {
+                (Some(usage), Some(limit)) => usage > limit,
+                _ => false,
+            };
+            let ok = !was_cpu_tle && !was_real_tle && !was_mle;
             if ok {
                 return false;
             }
@@ -83,6 +92,13 @@ pub(super) async fn watchdog(
                     "Real time limit exceeded"
                 );
                 let _ = chan.send(Event::RealTle);
+            } else if was_mle {
+                tracing::info!(
+                    usage = current_memory,
+                    limit = memory_limit,
+                    "Memory limit exceeded"
+                );
+                let _ = chan.send(Event::MemoryLimit);
             }
             let mut zyg = zygote.lock();
             {
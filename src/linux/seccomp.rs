@@ -1,32 +1,134 @@
+mod disasm;
+mod emit;
 #[rustfmt::skip]
 mod gen;
+pub(in crate::linux) mod notify;
+mod policies;
+pub(in crate::linux) mod rules;
+#[rustfmt::skip]
+mod syscall_names;
+
+pub use gen::PredefinedPolicy;
 
-use crate::linux::{util::get_last_error, SeccompPolicy};
+use crate::linux::{util::get_last_error, SeccompAction, SeccompPolicy, SeccompRules};
 use serde::{Deserialize, Serialize};
-use std::{convert::TryInto, marker::PhantomData};
+use std::{convert::TryInto, marker::PhantomData, os::unix::io::RawFd};
+
+/// Error returned when seccomp policy is invalid
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct PolicyError(String);
 
 #[derive(Clone, Serialize, Deserialize)]
-pub(in crate::linux) struct Seccomp(Vec<u8>);
+pub(in crate::linux) struct Seccomp {
+    /// BPF programs. All of them are installed in order, so the most
+    /// restrictive action wins. Order is chosen by `order_filters`.
+    filters: Vec<rules::Program>,
+    /// If true, notifications are recorded and syscalls are allowed.
+    audit: bool,
+}
 
 impl std::fmt::Debug for Seccomp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sizes = self
+            .filters
+            .iter()
+            .map(|program| program.filter.len())
+            .collect::<Vec<_>>();
         f.debug_struct("Seccomp")
-            .field("filter", &format_args!("{} bytes", self.0.len()))
+            .field("filters", &format_args!("{:?} bytes", sizes))
+            .field("reports_violations", &self.reports_violations())
+            .field("audit", &self.audit)
             .finish()
     }
 }
 
 impl Seccomp {
-    pub(in crate::linux) fn new(policy: &SeccompPolicy) -> Self {
-        match policy {
-            SeccompPolicy::Manual { policy } => (Seccomp(policy.clone())),
-            SeccompPolicy::DenyDangerous => Self(gen::DENY_DANGEROUS.to_vec()),
-            SeccompPolicy::Unrestricted => Self(gen::UNRESTRICTED.to_vec()),
-            SeccompPolicy::Pure => Self(gen::PURE.to_vec()),
+    pub(in crate::linux) fn new(policy: &SeccompPolicy) -> Result<Self, PolicyError> {
+        let program = match policy {
+            SeccompPolicy::Manual { policy } => {
+                disasm::check(policy)?;
+                policy.clone().into()
+            }
+            SeccompPolicy::DenyDangerous => pregenerated(PredefinedPolicy::DenyDangerous)?,
+            // no filter is installed, so this works on any architecture
+            SeccompPolicy::Unrestricted => {
+                return Ok(Seccomp {
+                    filters: Vec::new(),
+                    audit: false,
+                })
+            }
+            SeccompPolicy::Pure => pregenerated(PredefinedPolicy::Pure)?,
+            SeccompPolicy::Predefined(policy) => pregenerated(*policy)?,
+            SeccompPolicy::SingleProcess => {
+                let mut seccomp = Seccomp::new(&SeccompPolicy::DenyDangerous)?;
+                seccomp.deny_process_creation()?;
+                return Ok(seccomp);
+            }
+            SeccompPolicy::Rules { rules } => rules::compile(rules)?,
+            SeccompPolicy::Audit => {
+                if !crate::linux::check::seccomp_audit_supported() {
+                    return Err(PolicyError(
+                        "audit policy requires Linux 5.5 or newer".to_string(),
+                    ));
+                }
+                rules::compile_audit()?
+            }
+        };
+        if program.notify_fd_insn.is_some() && !crate::linux::check::seccomp_notify_supported() {
+            return Err(PolicyError(
+                "reporting violations is not supported by kernel".to_string(),
+            ));
         }
+        Ok(Seccomp {
+            filters: vec![program],
+            audit: matches!(policy, SeccompPolicy::Audit),
+        })
+    }
+
+    /// Additionally forbids creation of new processes (but not threads).
+    pub(in crate::linux) fn deny_process_creation(&mut self) -> Result<(), PolicyError> {
+        let mut rules = SeccompRules::new(SeccompAction::Allow);
+        policies::add_process_restrictions(&mut rules);
+        let mut filters = vec![rules::compile(&rules)?];
+        filters.append(&mut self.filters);
+        self.filters = order_filters(filters, self.audit)?;
+        Ok(())
     }
 
-    pub(in crate::linux) fn enable(&self) {
+    /// Returns policy which applies both `self` and `other`, so it is
+    /// at least as strict as each of them. Filters of `other` are
+    /// installed after filters of `self`, unless some filter of `self`
+    /// forbids installing them.
+    pub(in crate::linux) fn restrict(&self, other: Seccomp) -> Result<Seccomp, PolicyError> {
+        if self.reports_violations() && other.reports_violations() {
+            return Err(PolicyError(
+                "only one of stacked policies may report violations or audit syscalls".to_string(),
+            ));
+        }
+        let mut filters = self.filters.clone();
+        filters.extend(other.filters);
+        let audit = self.audit || other.audit;
+        Ok(Seccomp {
+            filters: order_filters(filters, audit)?,
+            audit,
+        })
+    }
+
+    /// If true, `enable` must be given a socket to send listener to.
+    pub(in crate::linux) fn reports_violations(&self) -> bool {
+        self.filters
+            .iter()
+            .any(|program| program.notify_fd_insn.is_some())
+    }
+
+    /// If true, notifications must be recorded and syscalls allowed
+    /// instead of killing processes.
+    pub(in crate::linux) fn is_audit(&self) -> bool {
+        self.audit
+    }
+
+    pub(in crate::linux) fn enable(&self, notify_sock: Option<RawFd>) {
         let ret = unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) };
         if ret != 0 {
             panic!(
@@ -34,26 +136,198 @@ impl Seccomp {
                 get_last_error()
             )
         }
-        let prog = LibcSockFprog {
-            len: (self.0.len() / 8).try_into().expect("too long program"),
-            prog: self.0.as_ptr().cast(),
-            phantom: PhantomData,
-        };
-        let ret = unsafe {
-            libc::syscall(
-                libc::SYS_seccomp,
-                // operation
-                SECCOMP_SET_MODE_FILTER,
-                // flags
-                0,
-                // prog
-                &prog as *const _,
-            )
+        for program in &self.filters {
+            if program.notify_fd_insn.is_none() {
+                if install(&program.filter, 0) == -1 {
+                    panic!("Failed to enable seccomp: {}", get_last_error())
+                }
+                continue;
+            }
+            let sock = notify_sock.expect("notification socket was not provided");
+            // patched before installation, because allocation may be forbidden
+            let filter = patch_notify_fd(program, sock);
+            let seal = program
+                .seal
+                .as_ref()
+                .map(|seal| patch_notify_fd(seal, sock));
+            let listener = install(&filter, notify::SECCOMP_FILTER_FLAG_NEW_LISTENER);
+            if listener == -1 {
+                panic!("Failed to enable seccomp: {}", get_last_error())
+            }
+            if let Err(err) = notify::send_listener(sock, listener as RawFd) {
+                panic!("Failed to send seccomp listener: {}", err)
+            }
+            if let Some(seal) = seal {
+                if install(&seal, 0) == -1 {
+                    panic!("Failed to enable seccomp: {}", get_last_error())
+                }
+            }
+        }
+    }
+}
+
+/// Checks that `program` (and its seal) lets syscall `nr` pass.
+fn allows(
+    program: &rules::Program,
+    audit: bool,
+    nr: i64,
+    args: [u64; 6],
+) -> Result<bool, PolicyError> {
+    let passes = |filter: &[u8]| -> Result<bool, PolicyError> {
+        let action = disasm::evaluate(filter, nr, args)?;
+        Ok(match action & emit::SECCOMP_RET_ACTION_FULL {
+            emit::SECCOMP_RET_ALLOW | emit::SECCOMP_RET_LOG => true,
+            // audited syscalls are allowed by zygote
+            emit::SECCOMP_RET_USER_NOTIF => audit,
+            _ => false,
+        })
+    };
+    if let Some(seal) = &program.seal {
+        if !passes(&seal.filter)? {
+            return Ok(false);
+        }
+    }
+    passes(&program.filter)
+}
+
+/// Orders filters so that each of them can be installed: filters installed
+/// earlier must allow seccomp(2), and filters installed before the one
+/// reporting violations must allow sending the listener. The only filter
+/// which forbids seccomp(2) is moved to the end, and the filter reporting
+/// violations is moved to the beginning if possible. Relative order of
+/// other filters is kept.
+fn order_filters(
+    filters: Vec<rules::Program>,
+    audit: bool,
+) -> Result<Vec<rules::Program>, PolicyError> {
+    let install_args = |flags: libc::c_ulong| [SECCOMP_SET_MODE_FILTER as u64, flags, 0, 0, 0, 0];
+    let mut permissive = Vec::new();
+    let mut last = Vec::new();
+    for program in filters {
+        if allows(&program, audit, libc::SYS_seccomp, install_args(0))? {
+            permissive.push(program);
+        } else {
+            last.push(program);
+        }
+    }
+    if let Some(pos) = permissive
+        .iter()
+        .position(|program| program.notify_fd_insn.is_some())
+    {
+        let reporting = permissive.remove(pos);
+        permissive.insert(0, reporting);
+    }
+    let mut ordered = permissive;
+    ordered.append(&mut last);
+
+    for (i, program) in ordered.iter().enumerate() {
+        let flags = if program.notify_fd_insn.is_some() {
+            notify::SECCOMP_FILTER_FLAG_NEW_LISTENER
+        } else {
+            0
         };
-        if ret != 0 {
-            panic!("Failed to enable seccomp: {}", get_last_error())
+        for earlier in &ordered[..i] {
+            if !allows(earlier, audit, libc::SYS_seccomp, install_args(flags))? {
+                return Err(PolicyError(
+                    "only one of stacked policies may forbid seccomp(2)".to_string(),
+                ));
+            }
+            // descriptor of notification socket is not known yet
+            if program.notify_fd_insn.is_some()
+                && !allows(earlier, audit, libc::SYS_sendmsg, [0; 6])?
+            {
+                return Err(PolicyError(
+                    "policy reporting violations forbids seccomp(2), \
+                    so it can not be stacked with policy forbidding sendmsg(2)"
+                        .to_string(),
+                ));
+            }
         }
     }
+    Ok(ordered)
+}
+
+/// Returns filter of `program` with notification socket descriptor filled in.
+fn patch_notify_fd(program: &rules::Program, sock: RawFd) -> Vec<u8> {
+    let mut filter = program.filter.clone();
+    if let Some(insn) = program.notify_fd_insn {
+        // `k` is the last field of `struct sock_filter`
+        let k = insn * 8 + 4;
+        filter[k..k + 4].copy_from_slice(&(sock as u32).to_ne_bytes());
+    }
+    filter
+}
+
+impl SeccompPolicy {
+    /// Returns human-readable listing of BPF programs this policy
+    /// consists of. Programs are listed in order they are installed.
+    pub fn disassemble(&self) -> Result<String, PolicyError> {
+        let seccomp = Seccomp::new(self)?;
+        let mut listing = String::new();
+        for (i, program) in seccomp.filters.iter().enumerate() {
+            listing.push_str(&format!("filter {}:\n", i));
+            listing.push_str(&disasm::disassemble(&program.filter)?);
+            if let Some(seal) = &program.seal {
+                listing.push_str(&format!("filter {} (seal):\n", i));
+                listing.push_str(&disasm::disassemble(&seal.filter)?);
+            }
+        }
+        Ok(listing)
+    }
+}
+
+impl From<Vec<u8>> for rules::Program {
+    fn from(filter: Vec<u8>) -> Self {
+        rules::Program {
+            filter,
+            notify_fd_insn: None,
+            seal: None,
+        }
+    }
+}
+
+/// Checks that filters of predefined policies exist for the target architecture.
+pub(in crate::linux) fn pregenerated_supported() -> bool {
+    !PredefinedPolicy::DenyDangerous.filter().is_empty()
+}
+
+/// Filters are generated only for some architectures, on others they are empty.
+fn pregenerated(policy: PredefinedPolicy) -> Result<rules::Program, PolicyError> {
+    let filter = policy.filter();
+    if filter.is_empty() {
+        return Err(PolicyError(
+            "target architecture is not supported".to_string(),
+        ));
+    }
+    Ok(filter.to_vec().into())
+}
+
+fn install(filter: &[u8], flags: libc::c_ulong) -> libc::c_long {
+    let prog = LibcSockFprog {
+        len: (filter.len() / 8).try_into().expect("too long program"),
+        prog: filter.as_ptr().cast(),
+        phantom: PhantomData,
+    };
+    unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            // operation
+            SECCOMP_SET_MODE_FILTER,
+            // flags
+            flags,
+            // prog
+            &prog as *const _,
+        )
+    }
+}
+
+/// Returns name of syscall for the native architecture
+pub(in crate::linux) fn syscall_name(nr: i64) -> Option<&'static str> {
+    let nr: usize = nr.try_into().ok()?;
+    syscall_names::NATIVE
+        .get(nr)
+        .copied()
+        .filter(|name| !name.is_empty())
 }
 
 const SECCOMP_SET_MODE_FILTER: i32 = 1;
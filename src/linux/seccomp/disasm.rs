@@ -0,0 +1,462 @@
+//! Implements validation and disassembly of seccomp BPF programs.
+//! Only instructions accepted by the kernel for seccomp filters
+//! are supported.
+use super::PolicyError;
+use std::{convert::TryInto, fmt::Write};
+
+const BPF_MAXINSNS: usize = 4096;
+/// `sizeof(struct seccomp_data)`
+const SECCOMP_DATA_SIZE: u32 = 64;
+const BPF_MEMWORDS: u32 = 16;
+
+#[derive(Copy, Clone)]
+struct Insn {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+#[derive(Copy, Clone)]
+enum AluOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Or,
+    And,
+    Lsh,
+    Rsh,
+    Mod,
+    Xor,
+}
+
+impl AluOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            AluOp::Add => "+",
+            AluOp::Sub => "-",
+            AluOp::Mul => "*",
+            AluOp::Div => "/",
+            AluOp::Or => "|",
+            AluOp::And => "&",
+            AluOp::Lsh => "<<",
+            AluOp::Rsh => ">>",
+            AluOp::Mod => "%",
+            AluOp::Xor => "^",
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+enum JmpOp {
+    Eq,
+    Gt,
+    Ge,
+    Set,
+}
+
+/// Decoded instruction. Jump targets are absolute.
+#[derive(Copy, Clone)]
+enum Op {
+    /// `A = seccomp_data[k]`
+    LoadData(u32),
+    LoadLen,
+    LoadXLen,
+    LoadImm(u32),
+    LoadXImm(u32),
+    LoadMem(u32),
+    LoadXMem(u32),
+    Store(u32),
+    StoreX(u32),
+    Tax,
+    Txa,
+    Alu(AluOp, Option<u32>),
+    Neg,
+    Ja(usize),
+    Jmp {
+        op: JmpOp,
+        /// `None` means comparison with X
+        k: Option<u32>,
+        jt: usize,
+        jf: usize,
+    },
+    Ret(u32),
+    RetA,
+}
+
+fn decode(pos: usize, insn: Insn, len: usize) -> Result<Op, PolicyError> {
+    let err = |msg: &str| PolicyError(format!("instruction {}: {}", pos, msg));
+    let target = |offset: u32| -> Result<usize, PolicyError> {
+        let target = pos + 1 + offset as usize;
+        if target >= len {
+            return Err(err("jump out of program"));
+        }
+        Ok(target)
+    };
+    let mem = |k: u32| {
+        if k >= BPF_MEMWORDS {
+            return Err(err("invalid scratch memory index"));
+        }
+        Ok(k)
+    };
+    let alu = |code: u16| match code & 0xf0 {
+        0x00 => Some(AluOp::Add),
+        0x10 => Some(AluOp::Sub),
+        0x20 => Some(AluOp::Mul),
+        0x30 => Some(AluOp::Div),
+        0x40 => Some(AluOp::Or),
+        0x50 => Some(AluOp::And),
+        0x60 => Some(AluOp::Lsh),
+        0x70 => Some(AluOp::Rsh),
+        0x90 => Some(AluOp::Mod),
+        0xa0 => Some(AluOp::Xor),
+        _ => None,
+    };
+    let k = insn.k;
+    let op = match insn.code {
+        0x20 => {
+            if k % 4 != 0 || k >= SECCOMP_DATA_SIZE {
+                return Err(err("invalid offset in seccomp_data"));
+            }
+            Op::LoadData(k)
+        }
+        0x80 => Op::LoadLen,
+        0x81 => Op::LoadXLen,
+        0x00 => Op::LoadImm(k),
+        0x01 => Op::LoadXImm(k),
+        0x60 => Op::LoadMem(mem(k)?),
+        0x61 => Op::LoadXMem(mem(k)?),
+        0x02 => Op::Store(mem(k)?),
+        0x03 => Op::StoreX(mem(k)?),
+        0x07 => Op::Tax,
+        0x87 => Op::Txa,
+        0x84 => Op::Neg,
+        0x06 => Op::Ret(k),
+        0x16 => Op::RetA,
+        0x05 => Op::Ja(target(k)?),
+        code if code & 0x07 == 0x04 => {
+            let op = alu(code).ok_or_else(|| err("unknown ALU operation"))?;
+            if code & 0x08 != 0 {
+                Op::Alu(op, None)
+            } else {
+                if matches!(op, AluOp::Div | AluOp::Mod) && k == 0 {
+                    return Err(err("division by zero"));
+                }
+                Op::Alu(op, Some(k))
+            }
+        }
+        code if code & 0x07 == 0x05 => {
+            let op = match code & 0xf0 {
+                0x10 => JmpOp::Eq,
+                0x20 => JmpOp::Gt,
+                0x30 => JmpOp::Ge,
+                0x40 => JmpOp::Set,
+                _ => return Err(err("unknown jump operation")),
+            };
+            Op::Jmp {
+                op,
+                k: if code & 0x08 != 0 { None } else { Some(k) },
+                jt: target(insn.jt.into())?,
+                jf: target(insn.jf.into())?,
+            }
+        }
+        code => return Err(err(&format!("unsupported opcode {:#x}", code))),
+    };
+    Ok(op)
+}
+
+/// Checks that program would be accepted by the kernel
+/// and decodes it.
+fn validate(filter: &[u8]) -> Result<Vec<Op>, PolicyError> {
+    if filter.len() % 8 != 0 {
+        return Err(PolicyError(
+            "program size is not a multiple of instruction size".to_string(),
+        ));
+    }
+    let len = filter.len() / 8;
+    if len == 0 || len > BPF_MAXINSNS {
+        return Err(PolicyError(format!("invalid program length {}", len)));
+    }
+    let ops = filter
+        .chunks(8)
+        .enumerate()
+        .map(|(pos, chunk)| {
+            let insn = Insn {
+                code: u16::from_ne_bytes(chunk[0..2].try_into().unwrap()),
+                jt: chunk[2],
+                jf: chunk[3],
+                k: u32::from_ne_bytes(chunk[4..8].try_into().unwrap()),
+            };
+            decode(pos, insn, len)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if !matches!(ops[len - 1], Op::Ret(_) | Op::RetA) {
+        return Err(PolicyError("program does not end with return".to_string()));
+    }
+    Ok(ops)
+}
+
+/// Checks that `filter` is a valid seccomp program.
+pub(super) fn check(filter: &[u8]) -> Result<(), PolicyError> {
+    validate(filter).map(drop)
+}
+
+/// Runs `filter` for syscall `nr` with given arguments, made using
+/// native ABI, and returns action chosen by the filter.
+pub(super) fn evaluate(filter: &[u8], nr: i64, args: [u64; 6]) -> Result<u32, PolicyError> {
+    let ops = validate(filter)?;
+    let arch = super::rules::AUDIT_ARCH_NATIVE
+        .ok_or_else(|| PolicyError("target architecture is not supported".to_string()))?;
+    // `struct seccomp_data`, instruction pointer is zero
+    let mut data = [0u8; SECCOMP_DATA_SIZE as usize];
+    data[0..4].copy_from_slice(&(nr as i32).to_ne_bytes());
+    data[4..8].copy_from_slice(&arch.to_ne_bytes());
+    for (i, arg) in args.iter().enumerate() {
+        data[16 + 8 * i..24 + 8 * i].copy_from_slice(&arg.to_ne_bytes());
+    }
+    let mut a = 0u32;
+    let mut x = 0u32;
+    let mut mem = [0u32; BPF_MEMWORDS as usize];
+    let mut pos = 0;
+    loop {
+        let mut next = pos + 1;
+        match ops[pos] {
+            Op::LoadData(offset) => {
+                let offset = offset as usize;
+                a = u32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap());
+            }
+            Op::LoadLen => a = SECCOMP_DATA_SIZE,
+            Op::LoadXLen => x = SECCOMP_DATA_SIZE,
+            Op::LoadImm(k) => a = k,
+            Op::LoadXImm(k) => x = k,
+            Op::LoadMem(k) => a = mem[k as usize],
+            Op::LoadXMem(k) => x = mem[k as usize],
+            Op::Store(k) => mem[k as usize] = a,
+            Op::StoreX(k) => mem[k as usize] = x,
+            Op::Tax => x = a,
+            Op::Txa => a = x,
+            Op::Alu(op, k) => {
+                let rhs = k.unwrap_or(x);
+                a = match op {
+                    AluOp::Add => a.wrapping_add(rhs),
+                    AluOp::Sub => a.wrapping_sub(rhs),
+                    AluOp::Mul => a.wrapping_mul(rhs),
+                    // kernel terminates program with zero on division by zero
+                    AluOp::Div => match a.checked_div(rhs) {
+                        Some(res) => res,
+                        None => return Ok(0),
+                    },
+                    AluOp::Mod => match a.checked_rem(rhs) {
+                        Some(res) => res,
+                        None => return Ok(0),
+                    },
+                    AluOp::Or => a | rhs,
+                    AluOp::And => a & rhs,
+                    AluOp::Xor => a ^ rhs,
+                    AluOp::Lsh => a.checked_shl(rhs).unwrap_or(0),
+                    AluOp::Rsh => a.checked_shr(rhs).unwrap_or(0),
+                };
+            }
+            Op::Neg => a = a.wrapping_neg(),
+            Op::Ja(target) => next = target,
+            Op::Jmp { op, k, jt, jf } => {
+                let rhs = k.unwrap_or(x);
+                let cond = match op {
+                    JmpOp::Eq => a == rhs,
+                    JmpOp::Gt => a > rhs,
+                    JmpOp::Ge => a >= rhs,
+                    JmpOp::Set => a & rhs != 0,
+                };
+                next = if cond { jt } else { jf };
+            }
+            Op::Ret(k) => return Ok(k),
+            Op::RetA => return Ok(a),
+        }
+        pos = next;
+    }
+}
+
+/// What is known about accumulator value
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Value {
+    Unknown,
+    Nr,
+    Arch,
+}
+
+fn data_field(offset: u32) -> String {
+    match offset {
+        0 => "sys_number".to_string(),
+        4 => "arch".to_string(),
+        8 => "instruction_pointer".to_string(),
+        12 => "instruction_pointer >> 32".to_string(),
+        _ => {
+            let arg = (offset - 16) / 8;
+            let high = (offset - 16) % 8 == 4;
+            // assumes little-endian layout of arguments
+            if high == cfg!(target_endian = "little") {
+                format!("args[{}] >> 32", arg)
+            } else {
+                format!("args[{}]", arg)
+            }
+        }
+    }
+}
+
+fn arch_name(audit_arch: u32) -> Option<&'static str> {
+    Some(match audit_arch {
+        0xc000_003e => "x86_64",
+        0x4000_0003 => "i386",
+        0xc000_00b7 => "aarch64",
+        0x4000_0028 => "arm",
+        _ => return None,
+    })
+}
+
+fn action(ret: u32) -> String {
+    let data = ret & 0xffff;
+    match ret & 0xffff_0000 {
+        0x8000_0000 => "KILL_PROCESS".to_string(),
+        0x0000_0000 => "KILL_THREAD".to_string(),
+        0x0003_0000 => format!("TRAP({})", data),
+        0x0005_0000 => format!("ERRNO({})", data),
+        0x7fc0_0000 => "USER_NOTIF".to_string(),
+        0x7ff0_0000 => format!("TRACE({})", data),
+        0x7ffc_0000 => "LOG".to_string(),
+        0x7fff_0000 => "ALLOW".to_string(),
+        _ => format!("unknown action {:#x}", ret),
+    }
+}
+
+fn operand(k: u32, value: Value) -> String {
+    match value {
+        Value::Nr => match super::syscall_name(k.into()) {
+            Some(name) => format!("{} /* {} */", k, name),
+            None => k.to_string(),
+        },
+        Value::Arch => match arch_name(k) {
+            Some(name) => format!("{:#x} /* {} */", k, name),
+            None => format!("{:#x}", k),
+        },
+        Value::Unknown => format!("{:#x}", k),
+    }
+}
+
+/// Returns human-readable listing of the program, one instruction
+/// per line. Comparisons with syscall numbers are annotated with
+/// syscall names.
+pub(super) fn disassemble(filter: &[u8]) -> Result<String, PolicyError> {
+    let ops = validate(filter)?;
+    // Jumps are forward-only, so accumulator state is known
+    // before instruction is processed.
+    let mut states: Vec<Option<Value>> = vec![None; ops.len()];
+    states[0] = Some(Value::Unknown);
+    let mut out = String::new();
+    for (pos, op) in ops.iter().enumerate() {
+        // unreachable instructions are still printed
+        let value = states[pos].unwrap_or(Value::Unknown);
+        let mut merge = |target: usize, value: Value| {
+            states[target] = match states[target] {
+                Some(old) if old != value => Some(Value::Unknown),
+                _ => Some(value),
+            };
+        };
+        write!(out, "{:04}: ", pos).unwrap();
+        let next = match *op {
+            Op::LoadData(offset) => {
+                write!(out, "A = {}", data_field(offset)).unwrap();
+                Some(match offset {
+                    0 => Value::Nr,
+                    4 => Value::Arch,
+                    _ => Value::Unknown,
+                })
+            }
+            Op::LoadLen => {
+                write!(out, "A = {}", SECCOMP_DATA_SIZE).unwrap();
+                Some(Value::Unknown)
+            }
+            Op::LoadXLen => {
+                write!(out, "X = {}", SECCOMP_DATA_SIZE).unwrap();
+                Some(value)
+            }
+            Op::LoadImm(k) => {
+                write!(out, "A = {:#x}", k).unwrap();
+                Some(Value::Unknown)
+            }
+            Op::LoadXImm(k) => {
+                write!(out, "X = {:#x}", k).unwrap();
+                Some(value)
+            }
+            Op::LoadMem(k) => {
+                write!(out, "A = mem[{}]", k).unwrap();
+                Some(Value::Unknown)
+            }
+            Op::LoadXMem(k) => {
+                write!(out, "X = mem[{}]", k).unwrap();
+                Some(value)
+            }
+            Op::Store(k) => {
+                write!(out, "mem[{}] = A", k).unwrap();
+                Some(value)
+            }
+            Op::StoreX(k) => {
+                write!(out, "mem[{}] = X", k).unwrap();
+                Some(value)
+            }
+            Op::Tax => {
+                write!(out, "X = A").unwrap();
+                Some(value)
+            }
+            Op::Txa => {
+                write!(out, "A = X").unwrap();
+                Some(Value::Unknown)
+            }
+            Op::Alu(op, k) => {
+                match k {
+                    Some(k) => write!(out, "A {}= {:#x}", op.symbol(), k).unwrap(),
+                    None => write!(out, "A {}= X", op.symbol()).unwrap(),
+                }
+                Some(Value::Unknown)
+            }
+            Op::Neg => {
+                write!(out, "A = -A").unwrap();
+                Some(Value::Unknown)
+            }
+            Op::Ja(target) => {
+                write!(out, "goto {:04}", target).unwrap();
+                merge(target, value);
+                None
+            }
+            Op::Jmp { op, k, jt, jf } => {
+                let rhs = match k {
+                    Some(k) => operand(k, value),
+                    None => "X".to_string(),
+                };
+                let cond = match op {
+                    JmpOp::Eq => format!("A == {}", rhs),
+                    JmpOp::Gt => format!("A > {}", rhs),
+                    JmpOp::Ge => format!("A >= {}", rhs),
+                    JmpOp::Set => format!("A & {}", rhs),
+                };
+                write!(out, "if ({}) goto {:04} else goto {:04}", cond, jt, jf).unwrap();
+                merge(jt, value);
+                merge(jf, value);
+                None
+            }
+            Op::Ret(k) => {
+                write!(out, "return {}", action(k)).unwrap();
+                None
+            }
+            Op::RetA => {
+                write!(out, "return A").unwrap();
+                None
+            }
+        };
+        if let Some(value) = next {
+            merge(pos + 1, value);
+        }
+        writeln!(out).unwrap();
+    }
+    Ok(out)
+}
@@ -0,0 +1,212 @@
+//! Classic BPF emitter for seccomp filters.
+//! This module is shared with `minion-codegen` (which includes it with
+//! `#[path]`), so it must only depend on `std`.
+//! All supported architectures are little-endian.
+use std::convert::TryFrom;
+
+// See linux/filter.h and linux/seccomp.h
+pub(super) const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_ALU_AND_K: u16 = 0x54;
+pub(super) const BPF_JEQ_K: u16 = 0x15;
+const BPF_JGT_K: u16 = 0x25;
+const BPF_JGE_K: u16 = 0x35;
+pub(super) const BPF_RET_K: u16 = 0x06;
+const BPF_MAXINSNS: usize = 4096;
+
+pub(super) const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+pub(super) const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+pub(super) const SECCOMP_RET_USER_NOTIF: u32 = 0x7fc0_0000;
+pub(super) const SECCOMP_RET_LOG: u32 = 0x7ffc_0000;
+pub(super) const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+pub(super) const SECCOMP_RET_ACTION_FULL: u32 = 0xffff_0000;
+
+// offsets in `struct seccomp_data`
+pub(super) const OFFSET_NR: u32 = 0;
+const OFFSET_ARCH: u32 = 4;
+pub(super) const OFFSET_ARGS: u32 = 16;
+
+/// Comparison of syscall argument with a constant, as unsigned 64-bit integers.
+#[derive(Copy, Clone)]
+pub(super) enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// `arg & mask == value`
+    MaskedEq(u64),
+}
+
+#[derive(Copy, Clone)]
+pub(super) struct Condition {
+    /// Argument number, from 0 to 5
+    pub(super) index: u8,
+    pub(super) cmp: Cmp,
+    pub(super) value: u64,
+}
+
+/// Returns `ret` if syscall number is `nr` and all `conditions` hold.
+pub(super) struct Rule {
+    pub(super) nr: u32,
+    pub(super) conditions: Vec<Condition>,
+    pub(super) ret: u32,
+}
+
+pub(super) fn emit(out: &mut Vec<u8>, code: u16, jt: u8, jf: u8, k: u32) {
+    out.extend_from_slice(&code.to_le_bytes());
+    out.push(jt);
+    out.push(jf);
+    out.extend_from_slice(&k.to_le_bytes());
+}
+
+/// Kills processes that use foreign syscall ABI (e.g. i386 `int 0x80`
+/// or x32 on x86_64, whose syscalls have `abi_bit` set).
+/// Leaves syscall number in the accumulator.
+pub(super) fn emit_arch_check(out: &mut Vec<u8>, audit_arch: u32, abi_bit: Option<u32>) {
+    emit(out, BPF_LD_W_ABS, 0, 0, OFFSET_ARCH);
+    emit(out, BPF_JEQ_K, 1, 0, audit_arch);
+    emit(out, BPF_RET_K, 0, 0, SECCOMP_RET_KILL_PROCESS);
+    emit(out, BPF_LD_W_ABS, 0, 0, OFFSET_NR);
+    if let Some(bit) = abi_bit {
+        emit(out, BPF_JGE_K, 0, 1, bit);
+        emit(out, BPF_RET_K, 0, 0, SECCOMP_RET_KILL_PROCESS);
+    }
+}
+
+/// Emits code which returns `ret` of the first matching rule, or `default`
+/// if no rule matches. `nr_loaded` tells if syscall number is already in
+/// the accumulator.
+pub(super) fn emit_rules(
+    out: &mut Vec<u8>,
+    rules: &[Rule],
+    default: u32,
+    mut nr_loaded: bool,
+) -> Result<(), String> {
+    for rule in rules {
+        let mut block = Block(Vec::new());
+        if !nr_loaded {
+            block.plain(BPF_LD_W_ABS, OFFSET_NR);
+        }
+        // conditions clobber the accumulator
+        nr_loaded = rule.conditions.is_empty();
+        block.jump(
+            BPF_JEQ_K,
+            rule.nr,
+            Target::At(block.0.len() + 1),
+            Target::Fail,
+        );
+        for cond in &rule.conditions {
+            if cond.index > 5 {
+                return Err(format!("invalid argument index {}", cond.index));
+            }
+            block.condition(cond);
+        }
+        block.plain(BPF_RET_K, rule.ret);
+        block.finish(out)?;
+    }
+    emit(out, BPF_RET_K, 0, 0, default);
+    if out.len() / 8 > BPF_MAXINSNS {
+        return Err("filter is too long".to_string());
+    }
+    Ok(())
+}
+
+/// Jump target inside of one rule
+#[derive(Copy, Clone)]
+enum Target {
+    /// Instruction with given index inside the rule
+    At(usize),
+    /// First instruction after the rule
+    Fail,
+}
+
+/// Instructions of one rule. Jumps are relative, so they are resolved
+/// when the rule is complete.
+struct Block(Vec<(u16, Target, Target, u32)>);
+
+impl Block {
+    fn plain(&mut self, code: u16, k: u32) {
+        let next = Target::At(self.0.len() + 1);
+        self.0.push((code, next, next, k));
+    }
+
+    fn jump(&mut self, code: u16, k: u32, jt: Target, jf: Target) {
+        self.0.push((code, jt, jf, k));
+    }
+
+    fn load_arg(&mut self, index: u8, high: bool) {
+        let offset = OFFSET_ARGS + 8 * u32::from(index) + if high { 4 } else { 0 };
+        self.plain(BPF_LD_W_ABS, offset);
+    }
+
+    /// Continues to the next condition if `(arg & mask) == value`.
+    fn masked_eq(&mut self, cond: &Condition, mask: u64) {
+        for &high in &[true, false] {
+            let shift = if high { 32 } else { 0 };
+            let half_mask = (mask >> shift) as u32;
+            let half_value = ((cond.value & mask) >> shift) as u32;
+            self.load_arg(cond.index, high);
+            if half_mask != u32::MAX {
+                self.plain(BPF_ALU_AND_K, half_mask);
+            }
+            let next = Target::At(self.0.len() + 1);
+            self.jump(BPF_JEQ_K, half_value, next, Target::Fail);
+        }
+    }
+
+    /// Continues to the next condition if result of `arg > value` (or
+    /// `arg >= value` if `or_equal` is set) equals `expected`.
+    fn compare(&mut self, cond: &Condition, or_equal: bool, expected: bool) {
+        let (hi, lo) = ((cond.value >> 32) as u32, cond.value as u32);
+        let start = self.0.len();
+        let next = Target::At(start + 5);
+        let (matched, not_matched) = if expected {
+            (next, Target::Fail)
+        } else {
+            (Target::Fail, next)
+        };
+        self.load_arg(cond.index, true);
+        self.jump(BPF_JGT_K, hi, matched, Target::At(start + 2));
+        self.jump(BPF_JEQ_K, hi, Target::At(start + 3), not_matched);
+        self.load_arg(cond.index, false);
+        let code = if or_equal { BPF_JGE_K } else { BPF_JGT_K };
+        self.jump(code, lo, matched, not_matched);
+    }
+
+    fn condition(&mut self, cond: &Condition) {
+        match cond.cmp {
+            Cmp::Eq => self.masked_eq(cond, u64::MAX),
+            Cmp::MaskedEq(mask) => self.masked_eq(cond, mask),
+            Cmp::Ne => {
+                // matches unless both halves are equal
+                let (hi, lo) = ((cond.value >> 32) as u32, cond.value as u32);
+                let start = self.0.len();
+                let next = Target::At(start + 4);
+                self.load_arg(cond.index, true);
+                self.jump(BPF_JEQ_K, hi, Target::At(start + 2), next);
+                self.load_arg(cond.index, false);
+                self.jump(BPF_JEQ_K, lo, Target::Fail, next);
+            }
+            Cmp::Gt => self.compare(cond, false, true),
+            Cmp::Ge => self.compare(cond, true, true),
+            Cmp::Lt => self.compare(cond, true, false),
+            Cmp::Le => self.compare(cond, false, false),
+        }
+    }
+
+    fn finish(self, out: &mut Vec<u8>) -> Result<(), String> {
+        let len = self.0.len();
+        for (i, (code, jt, jf, k)) in self.0.into_iter().enumerate() {
+            let offset = |target| {
+                let target = match target {
+                    Target::At(pos) => pos,
+                    Target::Fail => len,
+                };
+                u8::try_from(target - (i + 1)).map_err(|_| "rule is too long".to_string())
+            };
+            emit(out, code, offset(jt)?, offset(jf)?, k);
+        }
+        Ok(())
+    }
+}
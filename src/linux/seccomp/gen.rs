@@ -1,5 +1,61 @@
 // this is @generated file
 // codegen: minion-codegen/src/seccomp_policies.rs
-pub (in super) const UNRESTRICTED: &[u8] = &[32, 0, 0, 0, 4, 0, 0, 0, 21, 0, 0, 4, 62, 0, 0, 192, 32, 0, 0, 0, 0, 0, 0, 0, 53, 0, 0, 1, 0, 0, 0, 64, 21, 0, 0, 1, 255, 255, 255, 255, 6, 0, 0, 0, 0, 0, 255, 127, 6, 0, 0, 0, 0, 0, 0, 0];
-pub (in super) const DENY_DANGEROUS: &[u8] = &[32, 0, 0, 0, 4, 0, 0, 0, 21, 0, 0, 9, 62, 0, 0, 192, 32, 0, 0, 0, 0, 0, 0, 0, 53, 0, 0, 1, 0, 0, 0, 64, 21, 0, 0, 6, 255, 255, 255, 255, 21, 0, 4, 0, 62, 0, 0, 0, 21, 0, 3, 0, 101, 0, 0, 0, 21, 0, 2, 0, 54, 1, 0, 0, 21, 0, 1, 0, 55, 1, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 6, 0, 0, 0, 1, 0, 5, 0, 6, 0, 0, 0, 0, 0, 0, 0];
-pub (in super) const PURE: &[u8] = &[32, 0, 0, 0, 4, 0, 0, 0, 21, 0, 0, 13, 62, 0, 0, 192, 32, 0, 0, 0, 0, 0, 0, 0, 53, 0, 0, 1, 0, 0, 0, 64, 21, 0, 0, 10, 255, 255, 255, 255, 21, 0, 8, 0, 0, 0, 0, 0, 21, 0, 7, 0, 1, 0, 0, 0, 21, 0, 6, 0, 56, 0, 0, 0, 21, 0, 5, 0, 57, 0, 0, 0, 21, 0, 4, 0, 59, 0, 0, 0, 21, 0, 3, 0, 60, 0, 0, 0, 21, 0, 2, 0, 61, 0, 0, 0, 21, 0, 1, 0, 247, 0, 0, 0, 6, 0, 0, 0, 1, 0, 5, 0, 6, 0, 0, 0, 0, 0, 255, 127, 6, 0, 0, 0, 0, 0, 0, 0];
+// policies: minion-codegen/policies
+use serde::{Deserialize, Serialize};
+
+/// Policies defined in `minion-codegen/policies`.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PredefinedPolicy {
+    /// Allows everything except syscalls that affect other processes.
+    DenyDangerous,
+    /// Allows only basic syscalls (read, write, process management).
+    Pure,
+    /// Allows all syscalls of the native architecture.
+    Unrestricted,
+}
+
+impl PredefinedPolicy {
+    /// Returns filter for the target architecture
+    pub(super) fn filter(self) -> &'static [u8] {
+        match self {
+            PredefinedPolicy::DenyDangerous => native::DENY_DANGEROUS,
+            PredefinedPolicy::Pure => native::PURE,
+            PredefinedPolicy::Unrestricted => native::UNRESTRICTED,
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+use self::x86_64 as native;
+#[cfg(target_arch = "x86")]
+use self::x86 as native;
+#[cfg(target_arch = "aarch64")]
+use self::aarch64 as native;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64")))]
+mod native {
+    pub(in crate::linux::seccomp) const DENY_DANGEROUS: &[u8] = &[];
+    pub(in crate::linux::seccomp) const PURE: &[u8] = &[];
+    pub(in crate::linux::seccomp) const UNRESTRICTED: &[u8] = &[];
+}
+
+#[cfg(target_arch = "x86_64")]
+pub(super) mod x86_64 {
+    pub(in crate::linux::seccomp) const DENY_DANGEROUS: &[u8] = &[32, 0, 0, 0, 4, 0, 0, 0, 21, 0, 1, 0, 62, 0, 0, 192, 6, 0, 0, 0, 0, 0, 0, 128, 32, 0, 0, 0, 0, 0, 0, 0, 53, 0, 0, 1, 0, 0, 0, 64, 6, 0, 0, 0, 0, 0, 0, 128, 21, 0, 0, 1, 101, 0, 0, 0, 6, 0, 0, 0, 1, 0, 5, 0, 21, 0, 0, 1, 54, 1, 0, 0, 6, 0, 0, 0, 1, 0, 5, 0, 21, 0, 0, 1, 55, 1, 0, 0, 6, 0, 0, 0, 1, 0, 5, 0, 21, 0, 0, 1, 62, 0, 0, 0, 6, 0, 0, 0, 1, 0, 5, 0, 6, 0, 0, 0, 0, 0, 255, 127];
+    pub(in crate::linux::seccomp) const PURE: &[u8] = &[32, 0, 0, 0, 4, 0, 0, 0, 21, 0, 1, 0, 62, 0, 0, 192, 6, 0, 0, 0, 0, 0, 0, 128, 32, 0, 0, 0, 0, 0, 0, 0, 53, 0, 0, 1, 0, 0, 0, 64, 6, 0, 0, 0, 0, 0, 0, 128, 21, 0, 0, 1, 60, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 21, 0, 0, 1, 57, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 21, 0, 0, 1, 56, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 21, 0, 0, 1, 0, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 21, 0, 0, 1, 1, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 21, 0, 0, 1, 61, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 21, 0, 0, 1, 247, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 21, 0, 0, 1, 59, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 6, 0, 0, 0, 1, 0, 5, 0];
+    pub(in crate::linux::seccomp) const UNRESTRICTED: &[u8] = &[32, 0, 0, 0, 4, 0, 0, 0, 21, 0, 1, 0, 62, 0, 0, 192, 6, 0, 0, 0, 0, 0, 0, 128, 32, 0, 0, 0, 0, 0, 0, 0, 53, 0, 0, 1, 0, 0, 0, 64, 6, 0, 0, 0, 0, 0, 0, 128, 6, 0, 0, 0, 0, 0, 255, 127];
+}
+
+#[cfg(target_arch = "x86")]
+pub(super) mod x86 {
+    pub(in crate::linux::seccomp) const DENY_DANGEROUS: &[u8] = &[32, 0, 0, 0, 4, 0, 0, 0, 21, 0, 1, 0, 3, 0, 0, 64, 6, 0, 0, 0, 0, 0, 0, 128, 32, 0, 0, 0, 0, 0, 0, 0, 21, 0, 0, 1, 26, 0, 0, 0, 6, 0, 0, 0, 1, 0, 5, 0, 21, 0, 0, 1, 91, 1, 0, 0, 6, 0, 0, 0, 1, 0, 5, 0, 21, 0, 0, 1, 92, 1, 0, 0, 6, 0, 0, 0, 1, 0, 5, 0, 21, 0, 0, 1, 37, 0, 0, 0, 6, 0, 0, 0, 1, 0, 5, 0, 6, 0, 0, 0, 0, 0, 255, 127];
+    pub(in crate::linux::seccomp) const PURE: &[u8] = &[32, 0, 0, 0, 4, 0, 0, 0, 21, 0, 1, 0, 3, 0, 0, 64, 6, 0, 0, 0, 0, 0, 0, 128, 32, 0, 0, 0, 0, 0, 0, 0, 21, 0, 0, 1, 1, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 21, 0, 0, 1, 2, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 21, 0, 0, 1, 120, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 21, 0, 0, 1, 3, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 21, 0, 0, 1, 4, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 21, 0, 0, 1, 114, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 21, 0, 0, 1, 28, 1, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 21, 0, 0, 1, 11, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 6, 0, 0, 0, 1, 0, 5, 0];
+    pub(in crate::linux::seccomp) const UNRESTRICTED: &[u8] = &[32, 0, 0, 0, 4, 0, 0, 0, 21, 0, 1, 0, 3, 0, 0, 64, 6, 0, 0, 0, 0, 0, 0, 128, 32, 0, 0, 0, 0, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127];
+}
+
+#[cfg(target_arch = "aarch64")]
+pub(super) mod aarch64 {
+    pub(in crate::linux::seccomp) const DENY_DANGEROUS: &[u8] = &[32, 0, 0, 0, 4, 0, 0, 0, 21, 0, 1, 0, 183, 0, 0, 192, 6, 0, 0, 0, 0, 0, 0, 128, 32, 0, 0, 0, 0, 0, 0, 0, 21, 0, 0, 1, 117, 0, 0, 0, 6, 0, 0, 0, 1, 0, 5, 0, 21, 0, 0, 1, 14, 1, 0, 0, 6, 0, 0, 0, 1, 0, 5, 0, 21, 0, 0, 1, 15, 1, 0, 0, 6, 0, 0, 0, 1, 0, 5, 0, 21, 0, 0, 1, 129, 0, 0, 0, 6, 0, 0, 0, 1, 0, 5, 0, 6, 0, 0, 0, 0, 0, 255, 127];
+    pub(in crate::linux::seccomp) const PURE: &[u8] = &[32, 0, 0, 0, 4, 0, 0, 0, 21, 0, 1, 0, 183, 0, 0, 192, 6, 0, 0, 0, 0, 0, 0, 128, 32, 0, 0, 0, 0, 0, 0, 0, 21, 0, 0, 1, 93, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 21, 0, 0, 1, 220, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 21, 0, 0, 1, 63, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 21, 0, 0, 1, 64, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 21, 0, 0, 1, 4, 1, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 21, 0, 0, 1, 95, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 21, 0, 0, 1, 221, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127, 6, 0, 0, 0, 1, 0, 5, 0];
+    pub(in crate::linux::seccomp) const UNRESTRICTED: &[u8] = &[32, 0, 0, 0, 4, 0, 0, 0, 21, 0, 1, 0, 183, 0, 0, 192, 6, 0, 0, 0, 0, 0, 0, 128, 32, 0, 0, 0, 0, 0, 0, 0, 6, 0, 0, 0, 0, 0, 255, 127];
+}
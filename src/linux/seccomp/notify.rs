@@ -0,0 +1,190 @@
+//! Implements reporting of seccomp violations via `SECCOMP_RET_USER_NOTIF`.
+//! Sandboxed process installs filter with a listener and sends it to zygote,
+//! which receives notifications and kills offending processes.
+use crate::{
+    linux::{fd::Fd, util::Pid},
+    SecurityViolation,
+};
+use std::{io, mem, os::unix::io::RawFd, ptr};
+
+pub(super) const SECCOMP_FILTER_FLAG_NEW_LISTENER: libc::c_ulong = 1 << 3;
+const SECCOMP_GET_ACTION_AVAIL: libc::c_uint = 2;
+const SECCOMP_USER_NOTIF_FLAG_CONTINUE: u32 = 1;
+
+// _IOWR('!', 0, struct seccomp_notif) and _IOWR('!', 1, struct seccomp_notif_resp)
+const SECCOMP_IOCTL_NOTIF_RECV: libc::c_ulong = 0xc050_2100;
+const SECCOMP_IOCTL_NOTIF_SEND: libc::c_ulong = 0xc018_2101;
+// _IOR('!', 2, __u64): original (misdirected) value, accepted by all kernels
+const SECCOMP_IOCTL_NOTIF_ID_VALID: libc::c_ulong = 0x8008_2102;
+
+#[repr(C)]
+struct SeccompData {
+    nr: i32,
+    arch: u32,
+    instruction_pointer: u64,
+    args: [u64; 6],
+}
+
+#[repr(C)]
+struct SeccompNotif {
+    id: u64,
+    pid: u32,
+    flags: u32,
+    data: SeccompData,
+}
+
+#[repr(C)]
+struct SeccompNotifResp {
+    id: u64,
+    val: i64,
+    error: i32,
+    flags: u32,
+}
+
+/// Checks that kernel supports `SECCOMP_RET_USER_NOTIF` action.
+pub(in crate::linux) fn probe() -> bool {
+    let action = super::emit::SECCOMP_RET_USER_NOTIF;
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            SECCOMP_GET_ACTION_AVAIL,
+            0,
+            &action as *const u32,
+        )
+    };
+    ret == 0
+}
+
+/// Checks that kernel supports `SECCOMP_USER_NOTIF_FLAG_CONTINUE` (Linux 5.5).
+/// There is no way to detect it other than kernel version.
+pub(in crate::linux) fn probe_continue() -> bool {
+    let uname = nix::sys::utsname::uname();
+    let mut version = uname
+        .release()
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|part| part.parse::<u32>().unwrap_or(0));
+    let major = version.next().unwrap_or(0);
+    let minor = version.next().unwrap_or(0);
+    probe() && (major, minor) >= (5, 5)
+}
+
+/// Sends listener to zygote.
+/// Called after filter is installed, so it must only use `sendmsg`
+/// (which is allowed for the socket by the filter) and must not allocate.
+pub(super) fn send_listener(sock: RawFd, listener: RawFd) -> io::Result<()> {
+    // enough for one descriptor on all supported platforms
+    let mut cmsg_buf = [0u64; 4];
+    let mut data = [b'L'];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr().cast(),
+        iov_len: data.len(),
+    };
+    unsafe {
+        let cmsg_space = libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) as usize;
+        assert!(cmsg_space <= mem::size_of_val(&cmsg_buf));
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cmsg_buf.as_mut_ptr().cast();
+        msg.msg_controllen = cmsg_space as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<RawFd>() as u32) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast(), listener);
+        if libc::sendmsg(sock, &msg, 0) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Syscall made by sandboxed process, which waits for zygote decision.
+pub(in crate::linux) struct Notification(SeccompNotif);
+
+impl Notification {
+    pub(in crate::linux) fn syscall(&self) -> i64 {
+        i64::from(self.0.data.nr)
+    }
+
+    /// Kills offending process, its syscall fails.
+    pub(in crate::linux) fn deny(self, listener: &Fd) -> SecurityViolation {
+        // Notification pid is a thread id, which can be reused as soon as
+        // the thread dies. Everything about it is collected first, and then
+        // ID_VALID confirms that the thread is still blocked in the syscall,
+        // so the collected data refers to it.
+        let tid = self.0.pid as Pid;
+        let tgid = thread_group_id(tid).unwrap_or(tid);
+        let pidfd = if crate::linux::check::pidfd_supported() {
+            crate::linux::util::pidfd_open(tgid).ok().map(Fd::new)
+        } else {
+            None
+        };
+        // Process will not be able to handle the error, because it is killed
+        // before resuming. Errors are ignored, because process could have
+        // already been killed by someone else.
+        if is_valid(listener, self.0.id) {
+            match &pidfd {
+                Some(pidfd) => {
+                    crate::linux::util::pidfd_send_signal(pidfd.as_raw(), libc::SIGKILL).ok();
+                }
+                // pid could have been reused since the check
+                None => unsafe {
+                    libc::kill(tgid, libc::SIGKILL);
+                },
+            }
+        }
+        respond(listener, self.0.id, -libc::EPERM, 0);
+        let nr = self.syscall();
+        SecurityViolation {
+            pid: tgid as u32,
+            syscall: nr,
+            syscall_name: super::syscall_name(nr).map(ToString::to_string),
+            args: self.0.data.args,
+        }
+    }
+
+    /// Lets kernel execute the syscall as if there was no filter.
+    pub(in crate::linux) fn allow(self, listener: &Fd) {
+        respond(listener, self.0.id, 0, SECCOMP_USER_NOTIF_FLAG_CONTINUE);
+    }
+}
+
+/// Receives next notification.
+pub(in crate::linux) fn receive(listener: &Fd) -> io::Result<Notification> {
+    // kernel requires zeroed struct
+    let mut notif: SeccompNotif = unsafe { mem::zeroed() };
+    let ret = unsafe { libc::ioctl(listener.as_raw(), SECCOMP_IOCTL_NOTIF_RECV, &mut notif) };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Notification(notif))
+}
+
+/// Checks that notification is still pending, i.e. notifying thread
+/// has not been killed.
+fn is_valid(listener: &Fd, id: u64) -> bool {
+    unsafe { libc::ioctl(listener.as_raw(), SECCOMP_IOCTL_NOTIF_ID_VALID, &id) == 0 }
+}
+
+/// Returns pid of the process that thread `tid` belongs to.
+fn thread_group_id(tid: Pid) -> Option<Pid> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", tid)).ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Tgid:"))
+        .and_then(|tgid| tgid.trim().parse().ok())
+}
+
+fn respond(listener: &Fd, id: u64, error: i32, flags: u32) {
+    let resp = SeccompNotifResp {
+        id,
+        val: 0,
+        error,
+        flags,
+    };
+    // fails if process was killed while zygote was handling notification
+    unsafe {
+        libc::ioctl(listener.as_raw(), SECCOMP_IOCTL_NOTIF_SEND, &resp);
+    }
+}
@@ -0,0 +1,69 @@
+//! Built-in policies which are compiled at runtime.
+use crate::linux::{ArgOp, SeccompAction, SeccompRules, SyscallRule};
+
+#[cfg(target_arch = "x86_64")]
+const FORK_SYSCALLS: &[i64] = &[libc::SYS_fork, libc::SYS_vfork];
+// other architectures only have clone
+#[cfg(not(target_arch = "x86_64"))]
+const FORK_SYSCALLS: &[i64] = &[];
+
+const SYS_CLONE3: i64 = 435;
+
+/// Terminal queries and descriptor flags manipulation
+const ALLOWED_IOCTLS: &[u64] = &[
+    libc::TCGETS as u64,
+    libc::TIOCGWINSZ as u64,
+    libc::TIOCGPGRP as u64,
+    libc::FIONREAD as u64,
+    libc::FIONBIO as u64,
+    libc::FIOCLEX as u64,
+    libc::FIONCLEX as u64,
+];
+
+fn errno(code: i32) -> SeccompAction {
+    SeccompAction::Errno(code as u16)
+}
+
+/// Denies creation of processes, allowing threads. Also restricts
+/// `ioctl` requests and `socket` families.
+pub(super) fn add_process_restrictions(rules: &mut SeccompRules) {
+    // clone3 arguments are passed in memory and can not be inspected.
+    // ENOSYS makes libc fall back to clone.
+    rules.add_rule(SyscallRule::new(SYS_CLONE3, errno(libc::ENOSYS)));
+    for &call in FORK_SYSCALLS {
+        rules.add_rule(SyscallRule::new(call, errno(libc::EPERM)));
+    }
+    let clone_thread = libc::CLONE_THREAD as u64;
+    rules.add_rule(
+        SyscallRule::new(libc::SYS_clone, SeccompAction::Allow).with_arg(
+            0,
+            ArgOp::MaskedEq { mask: clone_thread },
+            clone_thread,
+        ),
+    );
+    rules.add_rule(SyscallRule::new(libc::SYS_clone, errno(libc::EPERM)));
+
+    // kernel only looks at lower 32 bits of ioctl request
+    for &request in ALLOWED_IOCTLS {
+        rules.add_rule(
+            SyscallRule::new(libc::SYS_ioctl, SeccompAction::Allow).with_arg(
+                1,
+                ArgOp::MaskedEq { mask: 0xffff_ffff },
+                request,
+            ),
+        );
+    }
+    rules.add_rule(SyscallRule::new(libc::SYS_ioctl, errno(libc::ENOTTY)));
+
+    rules.add_rule(
+        SyscallRule::new(libc::SYS_socket, SeccompAction::Allow).with_arg(
+            0,
+            ArgOp::Eq,
+            libc::AF_UNIX as u64,
+        ),
+    );
+    rules.add_rule(SyscallRule::new(
+        libc::SYS_socket,
+        errno(libc::EAFNOSUPPORT),
+    ));
+}
@@ -0,0 +1,283 @@
+//! Implements compiler of `SeccompRules` to classic BPF.
+use super::{
+    emit::{
+        self, emit, BPF_JEQ_K, BPF_LD_W_ABS, BPF_RET_K, OFFSET_ARGS, SECCOMP_RET_ALLOW,
+        SECCOMP_RET_ERRNO, SECCOMP_RET_KILL_PROCESS, SECCOMP_RET_USER_NOTIF,
+    },
+    PolicyError,
+};
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+
+/// What should happen when syscall matches a rule.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SeccompAction {
+    /// Syscall is executed normally
+    Allow,
+    /// Syscall is not executed and fails with given error code
+    Errno(u16),
+    /// Whole process is killed (as if by SIGSYS)
+    Kill,
+    /// Whole process is killed, and the syscall is reported by
+    /// `Sandbox::security_violations`.
+    /// Requires Linux 5.0.
+    Report,
+}
+
+/// Comparison of syscall argument with a constant.
+/// Arguments are compared as unsigned 64-bit integers.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ArgOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// `arg & mask == value`
+    MaskedEq {
+        mask: u64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ArgCondition {
+    /// Argument number, from 0 to 5
+    pub index: u8,
+    pub op: ArgOp,
+    pub value: u64,
+}
+
+/// Applies `action` to `syscall` if all `args` conditions hold.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct SyscallRule {
+    /// Syscall number for the native architecture (e.g. `libc::SYS_read`)
+    pub syscall: i64,
+    pub action: SeccompAction,
+    pub args: Vec<ArgCondition>,
+}
+
+impl SyscallRule {
+    pub fn new(syscall: i64, action: SeccompAction) -> Self {
+        SyscallRule {
+            syscall,
+            action,
+            args: Vec::new(),
+        }
+    }
+
+    /// Adds condition on argument `index`
+    pub fn with_arg(mut self, index: u8, op: ArgOp, value: u64) -> Self {
+        self.args.push(ArgCondition { index, op, value });
+        self
+    }
+}
+
+/// Seccomp policy, described as a list of rules.
+/// Rules are checked in order, and the first matching rule
+/// determines the action. If no rule matches, `default_action` is used.
+/// Several rules for the same syscall can be used to express alternatives.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct SeccompRules {
+    pub default_action: SeccompAction,
+    pub rules: Vec<SyscallRule>,
+}
+
+impl SeccompRules {
+    pub fn new(default_action: SeccompAction) -> Self {
+        SeccompRules {
+            default_action,
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn add_rule(&mut self, rule: SyscallRule) -> &mut Self {
+        self.rules.push(rule);
+        self
+    }
+
+    pub(super) fn uses_report(&self) -> bool {
+        let is_report = |action| action == SeccompAction::Report;
+        is_report(self.default_action) || self.rules.iter().any(|rule| is_report(rule.action))
+    }
+}
+
+const MAX_ERRNO: u16 = 4095;
+
+#[cfg(target_arch = "x86_64")]
+pub(super) const AUDIT_ARCH_NATIVE: Option<u32> = Some(0xc000_003e);
+#[cfg(target_arch = "x86")]
+pub(super) const AUDIT_ARCH_NATIVE: Option<u32> = Some(0x4000_0003);
+#[cfg(target_arch = "aarch64")]
+pub(super) const AUDIT_ARCH_NATIVE: Option<u32> = Some(0xc000_00b7);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64")))]
+pub(super) const AUDIT_ARCH_NATIVE: Option<u32> = None;
+
+/// x32 syscalls have this bit set on x86_64
+#[cfg(target_arch = "x86_64")]
+const ABI_BIT_NATIVE: Option<u32> = Some(0x4000_0000);
+#[cfg(not(target_arch = "x86_64"))]
+const ABI_BIT_NATIVE: Option<u32> = None;
+
+fn action_value(action: SeccompAction) -> Result<u32, PolicyError> {
+    Ok(match action {
+        SeccompAction::Allow => SECCOMP_RET_ALLOW,
+        SeccompAction::Kill => SECCOMP_RET_KILL_PROCESS,
+        SeccompAction::Report => SECCOMP_RET_USER_NOTIF,
+        SeccompAction::Errno(code) => {
+            if code > MAX_ERRNO {
+                return Err(PolicyError(format!("errno {} is too big", code)));
+            }
+            SECCOMP_RET_ERRNO | u32::from(code)
+        }
+    })
+}
+
+/// Compiled BPF program, represented as `struct sock_filter` array.
+#[derive(Clone, Serialize, Deserialize)]
+pub(super) struct Program {
+    pub(super) filter: Vec<u8>,
+    /// For programs reporting violations: index of the instruction which
+    /// must be patched with the notification socket descriptor
+    /// before program is installed.
+    pub(super) notify_fd_insn: Option<usize>,
+    /// For programs reporting violations: program which must be installed
+    /// right after the listener is sent. It revokes exceptions needed to
+    /// send the listener, so that they do not outlive the socket.
+    /// It is patched the same way as the main program.
+    pub(super) seal: Option<Box<Program>>,
+}
+
+pub(super) fn compile(rules: &SeccompRules) -> Result<Program, PolicyError> {
+    compile_program(rules, true)
+}
+
+/// Compiles program which reports every syscall. Such program does not
+/// need a seal, because audited syscalls are allowed anyway.
+pub(super) fn compile_audit() -> Result<Program, PolicyError> {
+    compile_program(&SeccompRules::new(SeccompAction::Report), false)
+}
+
+fn compile_program(rules: &SeccompRules, sealed: bool) -> Result<Program, PolicyError> {
+    let mut out = Vec::new();
+    emit_arch_check(&mut out)?;
+    if !rules.uses_report() {
+        emit_rules(&mut out, rules, true)?;
+        return Ok(Program {
+            filter: out,
+            notify_fd_insn: None,
+            seal: None,
+        });
+    }
+    // Listener is sent to zygote after the filter is installed,
+    // so sendmsg to the notification socket must be allowed.
+    // Seal is installed after that, so seccomp(2) must be allowed too.
+    // Descriptor is 32-bit, so high half of the argument is ignored.
+    if sealed {
+        emit(&mut out, BPF_JEQ_K, 3, 0, libc::SYS_seccomp as u32);
+    }
+    emit(&mut out, BPF_JEQ_K, 0, 3, libc::SYS_sendmsg as u32);
+    emit(&mut out, BPF_LD_W_ABS, 0, 0, OFFSET_ARGS);
+    let notify_fd_insn = Some(out.len() / 8);
+    emit(&mut out, BPF_JEQ_K, 0, 1, 0);
+    emit(&mut out, BPF_RET_K, 0, 0, SECCOMP_RET_ALLOW);
+    emit_rules(&mut out, rules, false)?;
+    let seal = if sealed {
+        Some(Box::new(compile_seal(rules)?))
+    } else {
+        None
+    };
+    Ok(Program {
+        filter: out,
+        notify_fd_insn,
+        seal,
+    })
+}
+
+/// Compiles program which applies `rules` to seccomp(2) and to sendmsg
+/// on the notification socket descriptor, and allows everything else.
+/// Such syscalls can not be reported (listener is not attached to this
+/// program), so they kill the process instead.
+fn compile_seal(rules: &SeccompRules) -> Result<Program, PolicyError> {
+    let without_report = |action| match action {
+        SeccompAction::Report => SeccompAction::Kill,
+        other => other,
+    };
+    let sealed_syscalls = [libc::SYS_seccomp, libc::SYS_sendmsg];
+    let mut seal_rules = SeccompRules::new(SeccompAction::Allow);
+    for rule in &rules.rules {
+        if sealed_syscalls.contains(&rule.syscall) {
+            seal_rules.add_rule(SyscallRule {
+                action: without_report(rule.action),
+                ..rule.clone()
+            });
+        }
+    }
+    for &syscall in &sealed_syscalls {
+        seal_rules.add_rule(SyscallRule::new(
+            syscall,
+            without_report(rules.default_action),
+        ));
+    }
+    let mut out = Vec::new();
+    emit_arch_check(&mut out)?;
+    emit(&mut out, BPF_JEQ_K, 5, 0, libc::SYS_seccomp as u32);
+    emit(&mut out, BPF_JEQ_K, 1, 0, libc::SYS_sendmsg as u32);
+    emit(&mut out, BPF_RET_K, 0, 0, SECCOMP_RET_ALLOW);
+    emit(&mut out, BPF_LD_W_ABS, 0, 0, OFFSET_ARGS);
+    let notify_fd_insn = Some(out.len() / 8);
+    emit(&mut out, BPF_JEQ_K, 1, 0, 0);
+    emit(&mut out, BPF_RET_K, 0, 0, SECCOMP_RET_ALLOW);
+    emit_rules(&mut out, &seal_rules, false)?;
+    Ok(Program {
+        filter: out,
+        notify_fd_insn,
+        seal: None,
+    })
+}
+
+/// Kills processes that use foreign syscall ABI.
+/// Leaves syscall number in the accumulator.
+fn emit_arch_check(out: &mut Vec<u8>) -> Result<(), PolicyError> {
+    let arch = AUDIT_ARCH_NATIVE
+        .ok_or_else(|| PolicyError("target architecture is not supported".to_string()))?;
+    emit::emit_arch_check(out, arch, ABI_BIT_NATIVE);
+    Ok(())
+}
+
+fn emit_rules(out: &mut Vec<u8>, rules: &SeccompRules, nr_loaded: bool) -> Result<(), PolicyError> {
+    let compiled = rules
+        .rules
+        .iter()
+        .map(|rule| {
+            let nr = rule
+                .syscall
+                .try_into()
+                .map_err(|_| PolicyError(format!("invalid syscall number {}", rule.syscall)))?;
+            let conditions = rule
+                .args
+                .iter()
+                .map(|cond| emit::Condition {
+                    index: cond.index,
+                    cmp: match cond.op {
+                        ArgOp::Eq => emit::Cmp::Eq,
+                        ArgOp::Ne => emit::Cmp::Ne,
+                        ArgOp::Lt => emit::Cmp::Lt,
+                        ArgOp::Le => emit::Cmp::Le,
+                        ArgOp::Gt => emit::Cmp::Gt,
+                        ArgOp::Ge => emit::Cmp::Ge,
+                        ArgOp::MaskedEq { mask } => emit::Cmp::MaskedEq(mask),
+                    },
+                    value: cond.value,
+                })
+                .collect();
+            Ok(emit::Rule {
+                nr,
+                conditions,
+                ret: action_value(rule.action)?,
+            })
+        })
+        .collect::<Result<Vec<_>, PolicyError>>()?;
+    let default = action_value(rules.default_action)?;
+    emit::emit_rules(out, &compiled, default, nr_loaded).map_err(PolicyError)
+}
@@ -3,7 +3,9 @@
 //! In particular, zygote is namespace root.
 //! Zygote accepts queries for spawning child process
 
+mod files;
 mod main_loop;
+pub(in crate::linux) mod mount_api;
 mod setup;
 
 use crate::linux::{
@@ -74,6 +76,8 @@ struct DoExecArg<'a> {
     jail_id: &'a str,
     setuid: bool,
     seccomp: &'a Seccomp,
+    /// Socket for sending seccomp listener to zygote
+    notify_sock: Option<RawFd>,
 }
 
 fn duplicate_string_list(v: &[OsString]) -> *mut *mut c_char {
@@ -215,7 +219,7 @@ fn do_exec(arg: DoExecArg) -> ! {
         )
         .unwrap();
 
-        arg.seccomp.enable();
+        arg.seccomp.enable(arg.notify_sock);
 
         libc::execvpe(
             path,
@@ -248,6 +252,7 @@ fn spawn_job(
     setuid: bool,
     resource_group_enter_handle: crate::linux::limits::OpaqueEnterHandle,
     seccomp: &Seccomp,
+    notify_sock: Option<RawFd>,
 ) -> Result<jail_common::JobStartupInfo, Error> {
     // `dea` will be passed to child process
     let dea = DoExecArg {
@@ -261,6 +266,7 @@ fn spawn_job(
         jail_id: &jail_id,
         setuid,
         seccomp: &seccomp,
+        notify_sock,
     };
     let res = unsafe { nix::unistd::fork() }?;
     let child_pid = match res {
@@ -286,6 +292,9 @@ pub(in crate::linux) fn start_zygote(
 
     match unsafe { nix::unistd::fork() }? {
         nix::unistd::ForkResult::Child => {
+            // otherwise zygote does not notice if the caller process dies,
+            // and keeps the sandbox alive (see `cleanup_stale_sandboxes`)
+            drop(client_socket);
             // why we use unshare(PID) here, and not in setup_namespace()? See pid_namespaces(7) and unshare(2)
             let unshare_ns = nix::sched::CloneFlags::CLONE_NEWUSER
                 | nix::sched::CloneFlags::CLONE_NEWPID
@@ -0,0 +1,73 @@
+//! Implements access to files inside sandbox on behalf of the host.
+//! Zygote is chrooted into the sandbox, so paths are resolved in the
+//! sandbox mount view.
+use crate::linux::{
+    fd::Fd, jail_common::OpenFileQuery, util::cvt_error, zygote::SANDBOX_INTERNAL_UID,
+};
+use nix::{
+    fcntl::OFlag,
+    sys::stat::{fstat, Mode, SFlag},
+};
+use std::{
+    io,
+    path::{Component, Path},
+};
+
+/// Opens `path` without following symlinks in any component.
+fn open_nofollow(path: &Path, flags: OFlag, mode: Mode) -> io::Result<Fd> {
+    let names = path
+        .components()
+        .filter_map(|comp| match comp {
+            Component::Normal(name) => Some(Ok(name)),
+            Component::RootDir | Component::CurDir => None,
+            Component::ParentDir | Component::Prefix(_) => Some(Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "path must not contain `..` components",
+            ))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let (last, parents) = names
+        .split_last()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path must not be empty"))?;
+    let dir_flags = OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC;
+    let mut dir = Fd::new(nix::fcntl::open("/", dir_flags, Mode::empty()).map_err(cvt_error)?);
+    for name in parents {
+        let fd = nix::fcntl::openat(dir.as_raw(), *name, dir_flags, Mode::empty());
+        dir = Fd::new(fd.map_err(cvt_error)?);
+    }
+    let flags = flags | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC;
+    let fd = nix::fcntl::openat(dir.as_raw(), *last, flags, mode).map_err(cvt_error)?;
+    Ok(Fd::new(fd))
+}
+
+/// Opens file for the host. Returned descriptor always refers to
+/// a regular file.
+pub(super) fn open_file(query: &OpenFileQuery, setuid: bool) -> io::Result<Fd> {
+    // O_NONBLOCK prevents sandboxed processes from blocking zygote with a FIFO
+    let (flags, mode) = match query.create_mode {
+        Some(mode) => (
+            OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC | OFlag::O_NONBLOCK,
+            // special bits (setuid, setgid, sticky) are never set
+            Mode::from_bits_truncate(mode & 0o777),
+        ),
+        None => (OFlag::O_RDONLY | OFlag::O_NONBLOCK, Mode::empty()),
+    };
+    let fd = open_nofollow(&query.path, flags, mode)?;
+    let stat = fstat(fd.as_raw()).map_err(cvt_error)?;
+    if SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT != SFlag::S_IFREG {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "path does not refer to a regular file",
+        ));
+    }
+    if query.create_mode.is_some() {
+        // mode passed to open() is affected by umask and ignored for existing files
+        nix::sys::stat::fchmod(fd.as_raw(), mode).map_err(cvt_error)?;
+        if setuid {
+            let uid = nix::unistd::Uid::from_raw(SANDBOX_INTERNAL_UID);
+            let gid = nix::unistd::Gid::from_raw(SANDBOX_INTERNAL_UID);
+            nix::unistd::fchown(fd.as_raw(), Some(uid), Some(gid)).map_err(cvt_error)?;
+        }
+    }
+    Ok(fd)
+}
@@ -1,17 +1,30 @@
-use crate::linux::{
-    fd::Fd,
-    jail_common::{JobQuery, Query, ResourceUsageInformation},
-    util::{Pid, StraceLogger},
-    zygote::{setup, spawn_job, JobOptions, Stdio, ZygoteOptions},
-    Error,
+use crate::{
+    linux::{
+        fd::Fd,
+        ipc::Socket,
+        jail_common::{JobQuery, LinuxSharedItem, OpenFileQuery, Query, ResourceUsageInformation},
+        seccomp::notify,
+        util::{Pid, StraceLogger},
+        zygote::{files, setup, spawn_job, JobOptions, Stdio, ZygoteOptions},
+        Error,
+    },
+    SecurityViolation,
 };
-use nix::sys::{
-    select::FdSet,
-    signal::{SigSet, Signal},
-    signalfd::SfdFlags,
-    wait::{WaitPidFlag, WaitStatus},
+use nix::{
+    poll::{PollFd, PollFlags},
+    sys::{
+        signal::{SigSet, Signal},
+        signalfd::SfdFlags,
+        wait::{WaitPidFlag, WaitStatus},
+    },
+};
+use std::{
+    collections::BTreeSet,
+    io::Write,
+    mem::MaybeUninit,
+    os::unix::io::RawFd,
+    path::{Path, PathBuf},
 };
-use std::{io::Write, mem::MaybeUninit};
 
 pub(crate) struct ReturnCode(i32);
 
@@ -36,10 +49,27 @@ struct Task {
     exit_code: Option<i32>,
 }
 
+struct Listener {
+    fd: Fd,
+    /// See `Seccomp::is_audit`
+    audit: bool,
+    /// Task that installed the filter. Filter is inherited, so
+    /// notifications can also come from descendants of the task.
+    task: Pid,
+}
+
 pub(crate) struct Zygote<'a, 'b> {
     tasks: Vec<Task>,
     options: &'a mut ZygoteOptions<'b>,
     resource_group_enter_handle: crate::linux::limits::OpaqueEnterHandle,
+    /// Destinations of items exposed after sandbox creation.
+    /// Only these can be unexposed.
+    live_exposed: Vec<PathBuf>,
+    /// Seccomp listeners of running tasks, if policy reports violations.
+    listeners: Vec<Listener>,
+    /// Violations with tasks they belong to
+    violations: Vec<(Pid, SecurityViolation)>,
+    used_syscalls: BTreeSet<i64>,
 }
 impl Zygote<'_, '_> {
     fn process_spawn_query(&mut self, options: &JobQuery) {
@@ -83,14 +113,36 @@ impl Zygote<'_, '_> {
         };
 
         writeln!(logger, "JobOptions are fetched").ok();
+        let seccomp = options
+            .seccomp
+            .as_ref()
+            .unwrap_or(&self.options.jail_options.seccomp);
+        let notify_socks = if seccomp.reports_violations() {
+            Some(Socket::pair().expect("failed to create a socket"))
+        } else {
+            None
+        };
         let startup_info = spawn_job(
             job_options,
             self.options.jail_options.jail_id.clone(),
             self.options.jail_options.sandbox_uid.is_some(),
             self.resource_group_enter_handle.clone(),
-            &self.options.jail_options.seccomp,
+            seccomp,
+            notify_socks
+                .as_ref()
+                .map(|(_, child)| child.inner().as_raw()),
         )
         .expect("failed to create child");
+        if let Some((mut sock, child_sock)) = notify_socks {
+            drop(child_sock);
+            // Fails if child died before enabling seccomp
+            if let Ok(listener) = sock.recv_fds(1) {
+                let audit = seccomp.is_audit();
+                let task = startup_info.pid;
+                self.listeners
+                    .extend(listener.into_iter().map(|fd| Listener { fd, audit, task }));
+            }
+        }
         writeln!(logger, "Job started, storing Task.").ok();
         let (notify, event) = if crate::linux::check::pidfd_supported() {
             (
@@ -197,15 +249,139 @@ impl Zygote<'_, '_> {
             let usage = usage.assume_init();
 
             let resp = ResourceUsageInformation {
-                // NOT total usage, but max usage
-                memory: usage.ru_maxrss as u64,
-                cpu: parse_timeval(usage.ru_utime) + parse_timeval(usage.ru_stime),
+                // NOT total usage, but max usage of the largest process.
+                // ru_maxrss is in kilobytes.
+                memory: usage.ru_maxrss as u64 * 1024,
+                user_time: parse_timeval(usage.ru_utime),
+                system_time: parse_timeval(usage.ru_stime),
+                major_page_faults: usage.ru_majflt as u64,
+                minor_page_faults: usage.ru_minflt as u64,
+                voluntary_context_switches: usage.ru_nvcsw as u64,
+                involuntary_context_switches: usage.ru_nivcsw as u64,
             };
             self.options.sock.send(&resp)?;
             Ok(())
         }
     }
 
+    fn process_expose_query(&mut self, item: &LinuxSharedItem) -> Result<(), Error> {
+        let tree = self.options.sock.recv_fds(1)?;
+        let res = setup::expose_item_live(item, &tree[0]).map_err(|err| err.to_string());
+        if res.is_ok() {
+            self.live_exposed.push(item.dest.clone());
+        }
+        self.options.sock.send(&res)?;
+        Ok(())
+    }
+
+    fn process_unexpose_query(&mut self, dest: &Path) -> Result<(), Error> {
+        // Items from `SandboxOptions` and procfs can not be unexposed.
+        let res = match self.live_exposed.iter().rposition(|item| item == dest) {
+            Some(pos) => setup::unexpose_item_live(dest)
+                .map(|_| {
+                    self.live_exposed.remove(pos);
+                })
+                .map_err(|err| err.to_string()),
+            None => Err(format!(
+                "{} is not an item exposed in running sandbox",
+                dest.display()
+            )),
+        };
+        self.options.sock.send(&res)?;
+        Ok(())
+    }
+
+    fn process_open_file_query(&mut self, query: &OpenFileQuery) -> Result<(), Error> {
+        let setuid = self.options.jail_options.sandbox_uid.is_some();
+        match files::open_file(query, setuid) {
+            Ok(fd) => {
+                self.options.sock.send(&Ok::<(), String>(()))?;
+                self.options.sock.send_fds(&[fd])?;
+            }
+            Err(err) => {
+                self.options
+                    .sock
+                    .send(&Err::<(), String>(err.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn process_security_violations_query(&mut self, task: Option<Pid>) -> Result<(), Error> {
+        let violations: Vec<SecurityViolation> = self
+            .violations
+            .iter()
+            .filter(|(pid, _)| task.map_or(true, |task| task == *pid))
+            .map(|(_, violation)| violation.clone())
+            .collect();
+        self.options.sock.send(&violations)?;
+        Ok(())
+    }
+
+    fn process_used_syscalls_query(&mut self) -> Result<(), Error> {
+        self.options.sock.send(&self.used_syscalls)?;
+        Ok(())
+    }
+
+    fn handle_listener_events(&mut self, events: &[Option<PollFlags>]) {
+        let mut logger = StraceLogger::new();
+        let mut i = 0;
+        for events in events {
+            let events = events.unwrap_or_else(PollFlags::empty);
+            if events.contains(PollFlags::POLLIN) {
+                let listener = &self.listeners[i];
+                match notify::receive(&listener.fd) {
+                    Ok(notification) if listener.audit => {
+                        self.used_syscalls.insert(notification.syscall());
+                        notification.allow(&listener.fd);
+                    }
+                    Ok(notification) => {
+                        let violation = notification.deny(&listener.fd);
+                        writeln!(logger, "zygote: {}", violation).ok();
+                        self.violations.push((listener.task, violation));
+                    }
+                    // notifying process could have been killed
+                    Err(err) => {
+                        writeln!(logger, "zygote: failed to receive notification: {}", err).ok();
+                    }
+                }
+            } else if !events.is_empty() {
+                // all processes using the filter have finished
+                self.listeners.remove(i);
+                continue;
+            }
+            i += 1;
+        }
+    }
+
+    /// Waits until a request can be received, handling other events.
+    /// `sig_fd` is used to reap children when pidfd is not supported.
+    fn wait_for_request(&mut self, sig_fd: Option<RawFd>) -> Result<(), Error> {
+        let sock_fd = self.options.sock.inner().as_raw();
+        loop {
+            let mut poll_fds = vec![PollFd::new(sock_fd, PollFlags::POLLIN)];
+            if let Some(sig_fd) = sig_fd {
+                poll_fds.push(PollFd::new(sig_fd, PollFlags::POLLIN));
+            }
+            let listeners_start = poll_fds.len();
+            for listener in &self.listeners {
+                poll_fds.push(PollFd::new(listener.fd.as_raw(), PollFlags::POLLIN));
+            }
+            match nix::poll::poll(&mut poll_fds, -1) {
+                Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+                res => res?,
+            };
+            let events = poll_fds.iter().map(|fd| fd.revents()).collect::<Vec<_>>();
+            self.handle_listener_events(&events[listeners_start..]);
+            if sig_fd.is_some() && events[1].map_or(false, |ev| !ev.is_empty()) {
+                self.reap_children()?;
+            }
+            if events[0].map_or(false, |ev| !ev.is_empty()) {
+                return Ok(());
+            }
+        }
+    }
+
     fn handle_one_request(&mut self) -> Result<Option<ReturnCode>, Error> {
         let mut logger = StraceLogger::new();
         let query: Query = match self.options.sock.recv() {
@@ -222,12 +398,18 @@ impl Zygote<'_, '_> {
             Query::Spawn(ref opts) => self.process_spawn_query(opts),
             Query::GetExitCode(query) => self.process_get_exit_code_query(query.pid)?,
             Query::GetResourceUsage => self.process_resource_usage_query()?,
+            Query::Expose(ref item) => self.process_expose_query(item)?,
+            Query::Unexpose(query) => self.process_unexpose_query(&query.dest)?,
+            Query::OpenFile(ref query) => self.process_open_file_query(query)?,
+            Query::GetSecurityViolations(task) => self.process_security_violations_query(task)?,
+            Query::GetUsedSyscalls => self.process_used_syscalls_query()?,
         };
         Ok(None)
     }
 
     fn run_loop_pidfd(&mut self) -> Result<ReturnCode, Error> {
         loop {
+            self.wait_for_request(None)?;
             if let Some(ret) = self.handle_one_request()? {
                 break Ok(ret);
             }
@@ -238,22 +420,10 @@ impl Zygote<'_, '_> {
         let mut sigset = SigSet::empty();
         sigset.add(Signal::SIGCHLD);
         let sig_fd = nix::sys::signalfd::signalfd(-1, &sigset, SfdFlags::SFD_CLOEXEC)?;
-        let sock_fd = self.options.sock.inner().as_raw();
         loop {
-            let mut fdset_read = FdSet::new();
-            if crate::linux::check::pidfd_supported() {
-                fdset_read.insert(sig_fd);
-            }
-            fdset_read.insert(sock_fd);
-
-            nix::sys::select::select(None, &mut fdset_read, None, None, None)?;
-            if fdset_read.contains(sig_fd) {
-                self.reap_children()?;
-            }
-            if fdset_read.contains(sock_fd) {
-                if let Some(ret) = self.handle_one_request()? {
-                    break Ok(ret);
-                }
+            self.wait_for_request(Some(sig_fd))?;
+            if let Some(ret) = self.handle_one_request()? {
+                break Ok(ret);
             }
         }
     }
@@ -266,6 +436,10 @@ pub(crate) fn entry(mut options: ZygoteOptions<'_>) -> Result<ReturnCode, Error>
         options: &mut options,
         tasks: Vec::new(),
         resource_group_enter_handle,
+        live_exposed: Vec::new(),
+        listeners: Vec::new(),
+        violations: Vec::new(),
+        used_syscalls: BTreeSet::new(),
     };
     if crate::linux::check::pidfd_supported() {
         zygote.run_loop_pidfd()
@@ -275,5 +449,5 @@ pub(crate) fn entry(mut options: ZygoteOptions<'_>) -> Result<ReturnCode, Error>
 }
 
 fn parse_timeval(tv: libc::timeval) -> u64 {
-    (tv.tv_usec + tv.tv_sec * 1_000_000_000) as u64
+    (tv.tv_usec * 1000 + tv.tv_sec * 1_000_000_000) as u64
 }
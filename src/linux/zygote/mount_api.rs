@@ -0,0 +1,307 @@
+//! Implements shared items exposition on top of the new mount API
+//! (`open_tree`, `move_mount` and `mount_setattr`).
+//! Bind targets are resolved with `openat2`, so symlinks inside isolation
+//! root can not redirect a mount outside of the jail.
+use crate::{
+    linux::{
+        fd::Fd,
+        jail_common::{MountPropagation, SharedItemFlags},
+        util::cvt_error,
+    },
+    SharedItemKind,
+};
+use nix::sys::stat::SFlag;
+use std::{
+    ffi::{CStr, CString},
+    io,
+    os::unix::{ffi::OsStrExt, io::RawFd},
+    path::{Component, Path},
+};
+
+const RESOLVE_NO_SYMLINKS: u64 = 0x04;
+const RESOLVE_IN_ROOT: u64 = 0x10;
+
+const OPEN_TREE_CLONE: libc::c_uint = 1;
+const AT_RECURSIVE: libc::c_uint = 0x8000;
+
+const MOVE_MOUNT_F_EMPTY_PATH: libc::c_uint = 0x04;
+const MOVE_MOUNT_T_EMPTY_PATH: libc::c_uint = 0x40;
+
+const MOUNT_ATTR_RDONLY: u64 = 0x01;
+const MOUNT_ATTR_NOSUID: u64 = 0x02;
+const MOUNT_ATTR_NODEV: u64 = 0x04;
+const MOUNT_ATTR_NOEXEC: u64 = 0x08;
+const MOUNT_ATTR_ATIME: u64 = 0x70;
+const MOUNT_ATTR_NOATIME: u64 = 0x10;
+
+#[repr(C)]
+struct OpenHow {
+    flags: u64,
+    mode: u64,
+    resolve: u64,
+}
+
+#[repr(C)]
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
+fn cvt(res: libc::c_long) -> io::Result<libc::c_long> {
+    if res == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res)
+    }
+}
+
+fn to_cstring(path: &Path) -> CString {
+    CString::new(path.as_os_str().as_bytes()).unwrap()
+}
+
+const EMPTY_PATH: &[u8] = b"\0";
+
+fn empty_path() -> &'static CStr {
+    CStr::from_bytes_with_nul(EMPTY_PATH).unwrap()
+}
+
+/// Opens `name` relative to `dir`, failing if any symlink is encountered.
+fn open_beneath(dir: RawFd, name: &CStr, flags: libc::c_int) -> io::Result<Fd> {
+    let how = OpenHow {
+        flags: (flags | libc::O_CLOEXEC) as u64,
+        mode: 0,
+        resolve: RESOLVE_IN_ROOT | RESOLVE_NO_SYMLINKS,
+    };
+    let fd = cvt(unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            dir,
+            name.as_ptr(),
+            &how as *const OpenHow,
+            std::mem::size_of::<OpenHow>(),
+        )
+    })?;
+    Ok(Fd::new(fd as RawFd))
+}
+
+fn ignore_exists(res: io::Result<()>) -> io::Result<()> {
+    match res {
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(()),
+        other => other,
+    }
+}
+
+fn mkdir_at(dir: &Fd, name: &CStr) -> io::Result<()> {
+    let res = cvt(unsafe { libc::mkdirat(dir.as_raw(), name.as_ptr(), 0o777) }.into());
+    ignore_exists(res.map(drop))
+}
+
+/// Creates regular file if it does not exist and returns `O_PATH`
+/// descriptor of it. Existing entries are never opened for writing,
+/// so sandboxed processes can not make zygote block on a FIFO or open
+/// a device node.
+fn create_file_at(dir: &Fd, name: &CStr) -> io::Result<Fd> {
+    let flags = libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+    let res = cvt(unsafe { libc::openat(dir.as_raw(), name.as_ptr(), flags, 0o666) }.into());
+    ignore_exists(res.map(|fd| drop(Fd::new(fd as RawFd))))?;
+    let file = open_beneath(dir.as_raw(), name, libc::O_PATH | libc::O_NOFOLLOW)?;
+    let stat = nix::sys::stat::fstat(file.as_raw()).map_err(cvt_error)?;
+    if SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT != SFlag::S_IFREG {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "bind target exists and is not a regular file",
+        ));
+    }
+    Ok(file)
+}
+
+/// Creates bind target and all its parents inside `root` and returns
+/// `O_PATH` descriptor of the target.
+fn create_bind_target(root: &Fd, alias_path: &Path, is_dir: bool) -> io::Result<Fd> {
+    let names = alias_path
+        .components()
+        .filter_map(|comp| match comp {
+            Component::Normal(name) => Some(Ok(name)),
+            Component::RootDir | Component::CurDir => None,
+            Component::ParentDir | Component::Prefix(_) => Some(Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "bind target must not contain `..` components",
+            ))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut current = open_beneath(
+        root.as_raw(),
+        CStr::from_bytes_with_nul(b".\0").unwrap(),
+        libc::O_PATH | libc::O_DIRECTORY,
+    )?;
+    for (i, name) in names.iter().enumerate() {
+        let name = CString::new(name.as_bytes()).unwrap();
+        let is_last = i + 1 == names.len();
+        current = if is_last && !is_dir {
+            create_file_at(&current, &name)?
+        } else {
+            mkdir_at(&current, &name)?;
+            open_beneath(current.as_raw(), &name, libc::O_PATH | libc::O_DIRECTORY)?
+        };
+    }
+    Ok(current)
+}
+
+fn mount_attr(kind: &SharedItemKind, flags: &SharedItemFlags) -> MountAttr {
+    let mut attr = MountAttr {
+        attr_set: 0,
+        attr_clr: 0,
+        propagation: 0,
+        userns_fd: 0,
+    };
+    if let SharedItemKind::Readonly = kind {
+        attr.attr_set |= MOUNT_ATTR_RDONLY;
+    }
+    if flags.noexec {
+        attr.attr_set |= MOUNT_ATTR_NOEXEC;
+    }
+    if flags.nosuid {
+        attr.attr_set |= MOUNT_ATTR_NOSUID;
+    }
+    if flags.nodev {
+        attr.attr_set |= MOUNT_ATTR_NODEV;
+    }
+    if flags.noatime {
+        attr.attr_clr |= MOUNT_ATTR_ATIME;
+        attr.attr_set |= MOUNT_ATTR_NOATIME;
+    }
+    if let Some(propagation) = flags.propagation {
+        attr.propagation = match propagation {
+            MountPropagation::Private => libc::MS_PRIVATE,
+            MountPropagation::Slave => libc::MS_SLAVE,
+        };
+    }
+    attr
+}
+
+fn open_dir(path: &Path) -> io::Result<Fd> {
+    let path = to_cstring(path);
+    let flags = libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC;
+    let fd = cvt(unsafe { libc::open(path.as_ptr(), flags) }.into())?;
+    Ok(Fd::new(fd as RawFd))
+}
+
+/// Creates detached copy of mount tree at `system_path` with mount
+/// attributes of the shared item applied.
+/// Returned descriptor can be attached in another mount namespace
+/// with `attach_tree`, which allows host to expose items without giving
+/// zygote access to the host filesystem.
+/// Unlike mount(2)-based implementation, mount attributes are applied
+/// to the whole tree when `recursive` flag is used.
+pub(in crate::linux) fn detached_tree(
+    system_path: &Path,
+    kind: &SharedItemKind,
+    flags: &SharedItemFlags,
+) -> io::Result<Fd> {
+    let mut tree_flags = OPEN_TREE_CLONE | libc::O_CLOEXEC as libc::c_uint;
+    let mut setattr_flags = libc::AT_EMPTY_PATH as libc::c_uint;
+    if flags.recursive {
+        tree_flags |= AT_RECURSIVE;
+        setattr_flags |= AT_RECURSIVE;
+    }
+    // symlinks are resolved, because mount(2) follows them as well
+    let tree = {
+        let system_path = to_cstring(system_path);
+        let fd = cvt(unsafe {
+            libc::syscall(
+                libc::SYS_open_tree,
+                libc::AT_FDCWD,
+                system_path.as_ptr(),
+                tree_flags,
+            )
+        })?;
+        Fd::new(fd as RawFd)
+    };
+
+    let attr = mount_attr(kind, flags);
+    if attr.attr_set != 0 || attr.attr_clr != 0 || attr.propagation != 0 {
+        cvt(unsafe {
+            libc::syscall(
+                libc::SYS_mount_setattr,
+                tree.as_raw(),
+                empty_path().as_ptr(),
+                setattr_flags,
+                &attr as *const MountAttr,
+                std::mem::size_of::<MountAttr>(),
+            )
+        })?;
+    }
+    Ok(tree)
+}
+
+/// Mounts tree returned by `detached_tree` to `alias_path` inside
+/// `jail_root`, creating the bind target if needed.
+pub(super) fn attach_tree(jail_root: &Path, tree: &Fd, alias_path: &Path) -> io::Result<()> {
+    let root = open_dir(jail_root)?;
+    let stat = nix::sys::stat::fstat(tree.as_raw()).map_err(cvt_error)?;
+    let is_dir = SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT == SFlag::S_IFDIR;
+    let target = create_bind_target(&root, alias_path, is_dir)?;
+    cvt(unsafe {
+        libc::syscall(
+            libc::SYS_move_mount,
+            tree.as_raw(),
+            empty_path().as_ptr(),
+            target.as_raw(),
+            empty_path().as_ptr(),
+            MOVE_MOUNT_F_EMPTY_PATH | MOVE_MOUNT_T_EMPTY_PATH,
+        )
+    })?;
+    Ok(())
+}
+
+/// Bind-mounts `system_path` to `alias_path` inside `jail_root`.
+pub(super) fn expose_item(
+    jail_root: &Path,
+    system_path: &Path,
+    alias_path: &Path,
+    kind: &SharedItemKind,
+    flags: &SharedItemFlags,
+) -> io::Result<()> {
+    let tree = detached_tree(system_path, kind, flags)?;
+    attach_tree(jail_root, &tree, alias_path)
+}
+
+/// Opens `path` inside `jail_root` without following symlinks in any
+/// component. If `path` is a mount point, returned descriptor refers
+/// to the root of the topmost mount.
+pub(super) fn open_in_root(jail_root: &Path, path: &Path) -> io::Result<Fd> {
+    let root = open_dir(jail_root)?;
+    let path = to_cstring(path);
+    open_beneath(root.as_raw(), &path, libc::O_PATH)
+}
+
+/// Checks that kernel implements `openat2` and `mount_setattr` (the latter
+/// is the newest syscall used by this module).
+pub(in crate::linux) fn probe() -> bool {
+    fn is_implemented(res: libc::c_long) -> bool {
+        !(res == -1 && errno::errno().0 == libc::ENOSYS)
+    }
+    // Both calls are made with invalid arguments and must fail with EINVAL.
+    let openat2_res = unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            libc::AT_FDCWD,
+            empty_path().as_ptr(),
+            std::ptr::null::<OpenHow>(),
+            0,
+        )
+    };
+    let setattr_res = unsafe {
+        libc::syscall(
+            libc::SYS_mount_setattr,
+            -1,
+            empty_path().as_ptr(),
+            0,
+            std::ptr::null::<MountAttr>(),
+            0,
+        )
+    };
+    is_implemented(openat2_res) && is_implemented(setattr_res)
+}
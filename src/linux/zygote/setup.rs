@@ -1,14 +1,14 @@
 use crate::{
     linux::{
         fd::Fd,
-        jail_common::{JailOptions, LinuxSharedItem, SharedItemFlags},
-        util::{err_exit, StraceLogger},
+        jail_common::{JailOptions, LinuxSharedItem, MountPropagation, SharedItemFlags},
+        util::{cvt_error, StraceLogger},
         zygote::SANDBOX_INTERNAL_UID,
         Error,
     },
     SharedItemKind,
 };
-use nix::sys::signal;
+use nix::sys::{signal, statvfs::FsFlags};
 use std::{ffi::CString, fs, io, io::Write, os::unix::ffi::OsStrExt, path::Path, ptr};
 
 fn configure_dir(dir_path: &Path) -> Result<(), Error> {
@@ -35,66 +35,184 @@ fn configure_dir(dir_path: &Path) -> Result<(), Error> {
     Ok(())
 }
 
+fn incompatible_bind_target(bind_target: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!(
+            "bind target ({}) already exists and has incompatible type",
+            bind_target.display()
+        ),
+    )
+}
+
+/// Creates an empty bind target of the same type as `system_path`,
+/// together with all parent directories.
+fn create_bind_target(bind_target: &Path, system_path: &Path) -> io::Result<()> {
+    if let Some(parent) = bind_target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // symlinks are resolved, because mount(2) follows them as well
+    let stat = fs::metadata(&system_path)?;
+    let res = if stat.is_dir() {
+        fs::create_dir(&bind_target)
+    } else {
+        // any other file type (including sockets and device nodes)
+        // can be mounted on top of a regular file
+        fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(&bind_target)
+            .map(drop)
+    };
+    match res {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => (),
+        Err(e) => return Err(e),
+    }
+    let target_stat = fs::symlink_metadata(&bind_target)?;
+    if target_stat.is_dir() != stat.is_dir() || target_stat.file_type().is_symlink() {
+        return Err(incompatible_bind_target(bind_target));
+    }
+    Ok(())
+}
+
+const LOCKED_MOUNT_FLAGS: &[(FsFlags, libc::c_ulong)] = &[
+    (FsFlags::ST_RDONLY, libc::MS_RDONLY),
+    (FsFlags::ST_NOSUID, libc::MS_NOSUID),
+    (FsFlags::ST_NODEV, libc::MS_NODEV),
+    (FsFlags::ST_NOEXEC, libc::MS_NOEXEC),
+    (FsFlags::ST_NOATIME, libc::MS_NOATIME),
+    (FsFlags::ST_NODIRATIME, libc::MS_NODIRATIME),
+    (FsFlags::ST_RELATIME, libc::MS_RELATIME),
+];
+
+/// Returns flags of mount at `path` which can not be cleared
+/// by remount in user namespace.
+fn locked_mount_flags(path: &Path) -> io::Result<libc::c_ulong> {
+    let st = nix::sys::statvfs::statvfs(path).map_err(cvt_error)?;
+    let mut flags = 0;
+    for &(st_flag, ms_flag) in LOCKED_MOUNT_FLAGS {
+        if st.flags().contains(st_flag) {
+            flags |= ms_flag;
+        }
+    }
+    Ok(flags)
+}
+
+fn mount(src: Option<&CString>, target: &CString, flags: libc::c_ulong) -> io::Result<()> {
+    let ret = unsafe {
+        libc::mount(
+            src.map_or(ptr::null(), |src| src.as_ptr()),
+            target.as_ptr(),
+            ptr::null(),
+            flags,
+            ptr::null(),
+        )
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
 fn expose_item(
     jail_root: &Path,
     system_path: &Path,
     alias_path: &Path,
-    kind: SharedItemKind,
+    kind: &SharedItemKind,
     flags: &SharedItemFlags,
-) {
+) -> io::Result<()> {
     let bind_target = jail_root.join(alias_path);
-    if let Err(e) = fs::create_dir_all(&bind_target) {
-        panic!(
-            "Failed to create bind target ({}): {}",
-            bind_target.display(),
-            e
-        );
-    }
-    let stat = fs::metadata(&system_path)
-        .unwrap_or_else(|err| panic!("failed to stat {}: {}", system_path.display(), err));
-    if stat.is_file() {
-        fs::remove_dir(&bind_target).unwrap();
-        fs::write(&bind_target, &"").unwrap();
-    }
-    let bind_target = CString::new(bind_target.as_os_str().as_bytes()).unwrap();
+    create_bind_target(&bind_target, system_path)?;
+    let bind_target_c = CString::new(bind_target.as_os_str().as_bytes()).unwrap();
     let bind_src = CString::new(system_path.as_os_str().as_bytes()).unwrap();
-    unsafe {
-        let mut mount_flags = libc::MS_BIND;
+
+    let mut mount_flags = libc::MS_BIND;
+    if flags.recursive {
+        mount_flags |= libc::MS_REC;
+    }
+    mount(Some(&bind_src), &bind_target_c, mount_flags)?;
+
+    if let Some(propagation) = flags.propagation {
+        let mut propagation_flags = match propagation {
+            MountPropagation::Private => libc::MS_PRIVATE,
+            MountPropagation::Slave => libc::MS_SLAVE,
+        };
         if flags.recursive {
-            mount_flags |= libc::MS_REC;
+            propagation_flags |= libc::MS_REC;
         }
-        let mnt_res = libc::mount(
-            bind_src.as_ptr(),
-            bind_target.as_ptr(),
-            ptr::null(),
-            mount_flags,
-            ptr::null(),
-        );
-        if mnt_res == -1 {
-            err_exit("mount");
-        }
-        mount_flags |= libc::MS_REMOUNT | libc::MS_RDONLY;
-
-        if let SharedItemKind::Readonly = kind {
-            let rem_ret = libc::mount(
-                ptr::null(),
-                bind_target.as_ptr(),
-                ptr::null(),
-                mount_flags,
-                ptr::null(),
-            );
-            if rem_ret == -1 {
-                err_exit("mount");
-            }
+        mount(None, &bind_target_c, propagation_flags)?;
+    }
+
+    let mut extra_flags = 0;
+    if let SharedItemKind::Readonly = kind {
+        extra_flags |= libc::MS_RDONLY;
+    }
+    if flags.noexec {
+        extra_flags |= libc::MS_NOEXEC;
+    }
+    if flags.nosuid {
+        extra_flags |= libc::MS_NOSUID;
+    }
+    if flags.nodev {
+        extra_flags |= libc::MS_NODEV;
+    }
+    if flags.noatime {
+        extra_flags |= libc::MS_NOATIME;
+    }
+    if extra_flags != 0 {
+        let mut locked_flags = locked_mount_flags(&bind_target)?;
+        if flags.noatime {
+            locked_flags &= !(libc::MS_RELATIME | libc::MS_NODIRATIME);
         }
+        mount_flags |= libc::MS_REMOUNT | extra_flags | locked_flags;
+        mount(None, &bind_target_c, mount_flags)?;
+    }
+    Ok(())
+}
+
+fn expose_item_any(jail_root: &Path, item: &LinuxSharedItem) -> io::Result<()> {
+    if crate::linux::check::new_mount_api_supported() {
+        super::mount_api::expose_item(jail_root, &item.src, &item.dest, &item.kind, &item.flags)
+    } else {
+        expose_item(jail_root, &item.src, &item.dest, &item.kind, &item.flags)
     }
 }
 
 pub(crate) fn expose_items(expose: &[LinuxSharedItem], jail_root: &Path) {
     // mount --bind
     for x in expose {
-        expose_item(jail_root, &x.src, &x.dest, x.kind.clone(), &x.flags)
+        if let Err(err) = expose_item_any(jail_root, x) {
+            panic!(
+                "Failed to expose {} as {}: {}",
+                x.src.display(),
+                x.dest.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Exposes item in a running sandbox.
+/// Must be called by zygote after `setup`. `tree` is detached mount
+/// created by the host (see `mount_api::detached_tree`), so zygote never
+/// needs access to the host filesystem.
+pub(in crate::linux) fn expose_item_live(item: &LinuxSharedItem, tree: &Fd) -> io::Result<()> {
+    super::mount_api::attach_tree(Path::new("/"), tree, &item.dest)
+}
+
+/// Unmounts previously exposed item in a running sandbox.
+pub(in crate::linux) fn unexpose_item_live(dest: &Path) -> io::Result<()> {
+    // any component can be replaced with a symlink by sandboxed processes
+    let target = super::mount_api::open_in_root(Path::new("/"), dest)?;
+    // this magic link refers to the topmost mount at `dest`
+    let target = format!("/proc/self/fd/{}", target.as_raw());
+    let target = CString::new(target).unwrap();
+    let ret = unsafe { libc::umount2(target.as_ptr(), libc::MNT_DETACH) };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
     }
+    Ok(())
 }
 
 extern "C" fn exit_sighandler(_code: i32) {
@@ -203,6 +321,11 @@ pub(in crate::linux) fn setup(
     }
     setup_expositions(&jail_params);
     setup_procfs(&jail_params)?;
+    // Zygote keeps host socket and seccomp listeners open, so sandboxed
+    // processes must not be able to access its descriptors.
+    if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) } == -1 {
+        return Err(io::Error::last_os_error().into());
+    }
     setup_chroot(&jail_params)?;
     let mut logger = crate::linux::util::StraceLogger::new();
     writeln!(logger, "sandbox {}: setup done", &jail_params.jail_id).unwrap();